@@ -1,14 +1,26 @@
 use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 
 use actix::prelude::*;
 use once_cell::sync::Lazy;
 use regex::Regex;
-use scraper::{ElementRef, Html};
+use scraper::{ElementRef, Html, Selector};
 use serde::{Deserialize, Serialize};
 
-use crate::caltrain_status::Error::{HtmlError, InvalidIntError};
+use crate::parse::{parse_named, ParseError};
 
 static NUMERIC: Lazy<Regex> = Lazy::new(|| Regex::new("[0-9]+").unwrap());
+static SUBTABLE: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("table.ipf-st-ip-trains-subtable").unwrap());
+static ANY_ROW: Lazy<Selector> = Lazy::new(|| Selector::parse("tr").unwrap());
+const ROW_CLASS: &str = "ipf-st-ip-trains-subtable-tr";
+static TRAIN_ID: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("td.ipf-st-ip-trains-subtable-td-id").unwrap());
+static TRAIN_TYPE: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("td.ipf-st-ip-trains-subtable-td-type").unwrap());
+static DEPARTURE: Lazy<Selector> =
+    Lazy::new(|| Selector::parse("td.ipf-st-ip-trains-subtable-td-arrivaltime").unwrap());
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialOrd, Ord, Eq, PartialEq, Debug)]
 pub enum TrainType {
@@ -17,16 +29,16 @@ pub enum TrainType {
     BabyBullet,
 }
 
-impl<T: AsRef<str>> From<T> for TrainType {
-    fn from(s: T) -> Self {
-        if s.as_ref().contains("Local") {
-            TrainType::Local
-        } else if s.as_ref().contains("Limited") {
-            TrainType::Limited
-        } else if s.as_ref().contains("Baby Bullet") {
-            TrainType::BabyBullet
+impl TrainType {
+    fn from_page_text(s: &str) -> Option<Self> {
+        if s.contains("Local") {
+            Some(TrainType::Local)
+        } else if s.contains("Limited") {
+            Some(TrainType::Limited)
+        } else if s.contains("Baby Bullet") {
+            Some(TrainType::BabyBullet)
         } else {
-            panic!("error, unknown train type: {}", s.as_ref());
+            None
         }
     }
 }
@@ -42,6 +54,31 @@ impl fmt::Display for TrainType {
     }
 }
 
+impl FromStr for TrainType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use TrainType::*;
+        let keys = |ttype: TrainType, extra: &[&str]| {
+            let mut keys = vec![format!("{:?}", ttype), ttype.to_string()];
+            keys.extend(extra.iter().map(|k| k.to_string()));
+            (ttype, ttype.to_string(), keys)
+        };
+        parse_named(
+            "train type",
+            s,
+            vec![
+                keys(Local, &[]),
+                keys(Limited, &[]),
+                keys(BabyBullet, &["Bullet", "BB"]),
+            ],
+        )
+    }
+}
+
+/// minutes till departure given to a train whose row doesn't have a countdown
+pub const NO_COUNTDOWN: u16 = 9001;
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct IncomingTrain {
     id: u16,
@@ -50,7 +87,7 @@ pub struct IncomingTrain {
 }
 
 impl IncomingTrain {
-    fn new(id: u16, ttype: TrainType, min_till_arrival: u16) -> Self {
+    pub(crate) fn new(id: u16, ttype: TrainType, min_till_arrival: u16) -> Self {
         IncomingTrain {
             id,
             ttype,
@@ -77,6 +114,26 @@ pub enum Direction {
     Southbound,
 }
 
+impl FromStr for Direction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = |direction: Direction, extra: &[&str]| {
+            let mut keys = vec![format!("{:?}", direction)];
+            keys.extend(extra.iter().map(|k| k.to_string()));
+            (direction, format!("{:?}", direction), keys)
+        };
+        parse_named(
+            "direction",
+            s,
+            vec![
+                keys(Direction::Northbound, &["North", "NB", "N"]),
+                keys(Direction::Southbound, &["South", "SB", "S"]),
+            ],
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct CaltrainStatus {
     northbound: Vec<IncomingTrain>,
@@ -89,153 +146,205 @@ impl CaltrainStatus {
     }
 
     pub fn from_html<T: AsRef<str>>(text: T) -> Result<CaltrainStatus, Error> {
-        struct WalkerState {
-            train_id: Option<String>,
-            train_type: Option<String>,
-            time_till_departure: Option<String>,
-            last_text: Option<String>,
-            current_table_no: i32,
-            last_read_class: Option<LastReadClass>,
-            northbound: Vec<IncomingTrain>,
-            southbound: Vec<IncomingTrain>,
-        }
-
-        let mut state = WalkerState {
-            train_id: None,
-            train_type: None,
-            time_till_departure: None,
-            last_text: None,
-            current_table_no: 0,
-            last_read_class: None,
-            southbound: vec![],
-            northbound: vec![],
-        };
-
-        #[derive(Clone, Copy)]
-        enum LastReadClass {
-            TrainId,
-            TrainType,
-            TimeTillArrival,
-        }
-
-        use LastReadClass::*;
-
         let dom = Html::parse_document(text.as_ref());
 
-        fn make_incoming_train(tid: &str, ttype: &str, tta: &str) -> Result<IncomingTrain, Error> {
-            let tid = tid.parse::<u16>()?;
-            let ttype = ttype.into();
-            let min_till_arrival = if let Some(m) = NUMERIC.find(&tta) {
-                m.as_str().parse::<u16>()?
-            } else {
-                9001
-            };
-            Ok(IncomingTrain::new(tid, ttype, min_till_arrival))
+        // the page lists southbound trains first, then northbound
+        let tables: Vec<ElementRef> = dom.select(&SUBTABLE).collect();
+        if tables.len() != 2 {
+            return Err(Error::TableNotFound {
+                found: tables.len(),
+            });
         }
 
-        fn walk(node: &ElementRef, state: &mut WalkerState) -> Result<(), Error> {
-            for attr in &node.value().attrs {
-                if &attr.0.local == "class" {
-                    let val = attr.1.as_bytes();
-                    if val.ends_with(b"ipf-st-ip-trains-subtable") {
-                        state.current_table_no += 1;
-                    }
-                    if val.ends_with(b"ipf-st-ip-trains-subtable-td-id") {
-                        state.last_read_class = Some(TrainId);
-                    }
-                    if val.ends_with(b"ipf-st-ip-trains-subtable-td-type") {
-                        state.last_read_class = Some(TrainType);
-                    }
-                    if val.ends_with(b"ipf-st-ip-trains-subtable-td-arrivaltime") {
-                        state.last_read_class = Some(TimeTillArrival);
-                    }
-                }
-            }
+        let mut row = 0;
+        let southbound = parse_table(tables[0], &mut row)?;
+        let northbound = parse_table(tables[1], &mut row)?;
 
-            for child in node.children() {
-                if let Some(e) = ElementRef::wrap(child) {
-                    walk(&e, state)?;
-                } else {
-                    if let Some(t) = child.value().as_text() {
-                        state.last_text = Some(t.text.to_string());
-                    }
-                }
-            }
-            let res = match (&state.last_read_class, &state.last_text) {
-                (Some(ttype), Some(text)) => {
-                    match ttype {
-                        TrainId => state.train_id = Some(text.clone()),
-                        TrainType => state.train_type = Some(text.clone()),
-                        TimeTillArrival => state.time_till_departure = Some(text.clone()),
-                    }
-                    (None, None)
-                }
-                (a, b) => (*a, b.as_ref().cloned()),
-            };
-            state.last_read_class = res.0;
-            state.last_text = res.1;
-            let mut should_wipe = false;
-            if let (Some(tid), Some(ttype), Some(tta)) = (
-                &mut state.train_id,
-                &mut state.train_type,
-                &mut state.time_till_departure,
-            ) {
-                if state.current_table_no == 1 {
-                    state.southbound.push(make_incoming_train(tid, ttype, tta)?);
-                }
-                if state.current_table_no == 2 {
-                    state.northbound.push(make_incoming_train(tid, ttype, tta)?);
-                }
-                should_wipe = true;
-            }
+        Ok(CaltrainStatus {
+            northbound,
+            southbound,
+        })
+    }
+}
 
-            if should_wipe {
-                state.train_id = None;
-                state.train_type = None;
-                state.time_till_departure = None;
-            }
-            Ok(())
+fn parse_table(table: ElementRef, row: &mut usize) -> Result<Vec<IncomingTrain>, Error> {
+    let mut trains = vec![];
+    // rows that lost their class would otherwise be skipped, which reads as "no trains"
+    for tr in table.select(&ANY_ROW) {
+        if !tr.value().classes().any(|class| class == ROW_CLASS) {
+            return Err(Error::UnrecognizedRow {
+                row: *row,
+                raw: row_text(tr),
+            });
         }
+        trains.push(parse_row(tr, *row)?);
+        *row += 1;
+    }
+    Ok(trains)
+}
 
-        walk(&dom.root_element(), &mut state)?;
+fn row_text(tr: ElementRef) -> String {
+    tr.text().collect::<Vec<_>>().join(" ")
+}
 
-        Ok(CaltrainStatus {
-            northbound: state.northbound,
-            southbound: state.southbound,
-        })
-    }
+fn parse_row(tr: ElementRef, row: usize) -> Result<IncomingTrain, Error> {
+    let raw = || row_text(tr);
+    let field = |selector: &Selector, name: &'static str| {
+        tr.select(selector)
+            .next()
+            .map(|td| td.text().collect::<String>().trim().to_string())
+            .filter(|text| !text.is_empty())
+            .ok_or_else(|| Error::RowMissingField {
+                row,
+                field: name,
+                raw: raw(),
+            })
+    };
+
+    let tid = field(&TRAIN_ID, "id")?;
+    let ttype = field(&TRAIN_TYPE, "type")?;
+    let tta = field(&DEPARTURE, "departure time")?;
+
+    let tid = tid.parse::<u16>().map_err(|source| Error::InvalidNumber {
+        row,
+        raw: tid.clone(),
+        source,
+    })?;
+    let ttype = TrainType::from_page_text(&ttype).ok_or_else(|| Error::UnknownTrainType {
+        row,
+        raw: ttype.clone(),
+    })?;
+    let min_till_arrival = if let Some(m) = NUMERIC.find(&tta) {
+        m.as_str()
+            .parse::<u16>()
+            .map_err(|source| Error::InvalidNumber {
+                row,
+                raw: tta.clone(),
+                source,
+            })?
+    } else {
+        NO_COUNTDOWN
+    };
+    Ok(IncomingTrain::new(tid, ttype, min_till_arrival))
 }
 
 impl Message for CaltrainStatus {
     type Result = ();
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Error {
-    HtmlError(std::io::Error),
-    InvalidIntError(std::num::ParseIntError),
+    HttpStatus(u16),
+    Transport(String),
+    Timeout(Duration),
+    Encoding(std::string::FromUtf8Error),
+    TableNotFound {
+        found: usize,
+    },
+    UnrecognizedRow {
+        row: usize,
+        raw: String,
+    },
+    RowMissingField {
+        row: usize,
+        field: &'static str,
+        raw: String,
+    },
+    InvalidNumber {
+        row: usize,
+        raw: String,
+        source: std::num::ParseIntError,
+    },
+    UnknownTrainType {
+        row: usize,
+        raw: String,
+    },
 }
 
-impl std::error::Error for Error {}
+impl Error {
+    /// short stable name for the failure, suitable for logs and the control api
+    pub fn kind(&self) -> &'static str {
+        use Error::*;
+        match self {
+            HttpStatus(_) => "http_status",
+            Transport(_) => "transport",
+            Timeout(_) => "timeout",
+            Encoding(_) => "encoding",
+            TableNotFound { .. } => "table_not_found",
+            UnrecognizedRow { .. } => "unrecognized_row",
+            RowMissingField { .. } => "row_missing_field",
+            InvalidNumber { .. } => "invalid_number",
+            UnknownTrainType { .. } => "unknown_train_type",
+        }
+    }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// process exit code, network problems are 10-19 and scraper problems are 20-29 so scripts
+    /// can tell an outage from a caltrain markup change
+    pub fn exit_code(&self) -> i32 {
+        use Error::*;
         match self {
-            HtmlError(e) => write!(f, "{:?}", e),
-            InvalidIntError(e) => write!(f, "{}", e),
+            HttpStatus(_) => 10,
+            Transport(_) => 11,
+            Timeout(_) => 12,
+            Encoding(_) => 20,
+            TableNotFound { .. } => 21,
+            RowMissingField { .. } => 22,
+            InvalidNumber { .. } => 23,
+            UnknownTrainType { .. } => 24,
+            UnrecognizedRow { .. } => 25,
+        }
+    }
+
+    /// true when the page came back but no longer looks the way the parser expects, as opposed
+    /// to caltrain being unreachable
+    pub fn is_markup_change(&self) -> bool {
+        use Error::*;
+        match self {
+            HttpStatus(_) | Transport(_) | Timeout(_) => false,
+            Encoding(_)
+            | TableNotFound { .. }
+            | UnrecognizedRow { .. }
+            | RowMissingField { .. }
+            | InvalidNumber { .. }
+            | UnknownTrainType { .. } => true,
         }
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(e: std::io::Error) -> Self {
-        HtmlError(e)
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            HttpStatus(code) => write!(f, "caltrain responded with http status {}", code),
+            Transport(e) => write!(f, "error making request to caltrain: {}", e),
+            Timeout(d) => write!(f, "request to caltrain timed out after {}s", d.as_secs()),
+            Encoding(e) => write!(f, "response from caltrain was not valid utf-8: {}", e),
+            TableNotFound { found } => write!(
+                f,
+                "expected 2 train tables in caltrain page, found {}",
+                found
+            ),
+            UnrecognizedRow { row, raw } => {
+                write!(f, "row {} is not a recognized train row: {:?}", row, raw)
+            }
+            RowMissingField { row, field, raw } => {
+                write!(f, "row {} is missing train {}: {:?}", row, field, raw)
+            }
+            InvalidNumber { row, raw, source } => {
+                write!(f, "row {} has invalid number {:?}: {}", row, raw, source)
+            }
+            UnknownTrainType { row, raw } => {
+                write!(f, "row {} has unknown train type {:?}", row, raw)
+            }
+        }
     }
 }
 
-impl From<std::num::ParseIntError> for Error {
-    fn from(e: std::num::ParseIntError) -> Self {
-        InvalidIntError(e)
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Error::Encoding(e)
     }
 }
 
@@ -312,4 +421,71 @@ mod test {
             }
         )
     }
+
+    #[test]
+    fn from_html_no_trains() {
+        let html = r#"<div id="ipsttrains"><table class="ipf-caltrain-table-trains"><tr>
+            <td><table class="ipf-st-ip-trains-subtable"></table></td>
+            <td><table class="ipf-st-ip-trains-subtable"></table></td>
+            </tr></table></div>"#;
+        assert_eq!(
+            CaltrainStatus::from_html(html).unwrap(),
+            CaltrainStatus {
+                northbound: vec![],
+                southbound: vec![],
+            }
+        )
+    }
+
+    #[test]
+    fn from_html_renamed_rows() {
+        let html = include_str!("test2.html").replace(
+            "ipf-st-ip-trains-subtable-tr",
+            "ipf-st-ip-trains-subtable-row",
+        );
+        match CaltrainStatus::from_html(html) {
+            Err(Error::UnrecognizedRow { row: 0, .. }) => (),
+            other => panic!("expected unrecognized row error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_train_type_and_direction() {
+        assert_eq!("baby bullet".parse(), Ok(TrainType::BabyBullet));
+        assert_eq!("BabyBullet".parse(), Ok(TrainType::BabyBullet));
+        assert_eq!("local".parse(), Ok(TrainType::Local));
+        assert_eq!("nb".parse(), Ok(Direction::Northbound));
+        assert_eq!("southbound".parse(), Ok(Direction::Southbound));
+        assert!("Lmited".parse::<TrainType>().is_err());
+    }
+
+    #[test]
+    fn from_html_missing_tables() {
+        match CaltrainStatus::from_html("<html><body></body></html>") {
+            Err(Error::TableNotFound { found: 0 }) => (),
+            other => panic!("expected missing table error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn from_html_unknown_train_type() {
+        let html = include_str!("test.html").replacen("Baby Bullet", "Hyperloop", 1);
+        match CaltrainStatus::from_html(html) {
+            Err(Error::UnknownTrainType { row: 0, raw }) => assert_eq!(raw, "Hyperloop"),
+            other => panic!("expected unknown train type error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exit_codes_split_outages_from_markup_changes() {
+        let outage = Error::Timeout(Duration::from_secs(15));
+        assert_eq!(outage.exit_code(), 12);
+        assert!(!outage.is_markup_change());
+        let changed = Error::UnrecognizedRow {
+            row: 3,
+            raw: String::new(),
+        };
+        assert_eq!(changed.exit_code(), 25);
+        assert!(changed.is_markup_change());
+    }
 }
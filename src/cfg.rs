@@ -1,19 +1,235 @@
-use std::fs::create_dir_all;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs::{
+    metadata, read_dir, read_to_string, rename, set_permissions, write, DirBuilder, Permissions,
+};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 
-use once_cell::sync::Lazy;
+use nix::unistd::getuid;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
 
-pub static CALTRAIND_PATH: Lazy<PathBuf> = Lazy::new(|| {
-    let p = PathBuf::from("/tmp/caltraind");
-    create_dir_all(&p).expect("error creating /tmp/caltraind");
-    p
+pub const DEFAULT_INSTANCE: &str = "default";
+pub const PID_FILE: &str = "pid";
+pub const CMDLINE_FILE: &str = "cmdline";
+
+/// directories picked by `init_dirs`
+struct Dirs {
+    runtime: PathBuf,
+    state: PathBuf,
+    instance: String,
+}
+
+static DIRS: OnceCell<Dirs> = OnceCell::new();
+
+fn dirs() -> &'static Dirs {
+    DIRS.get_or_init(|| {
+        resolve_dirs(None, None, None, &Config::default()).unwrap_or_else(|e| panic!("{}", e))
+    })
+}
+
+/// picks where the socket, pid file, logs and saved data go and which instance they belong to,
+/// preferring the command line, then `CALTRAIND_RUNTIME_DIR`, `CALTRAIND_STATE_DIR` and
+/// `CALTRAIND_INSTANCE`, then the config file, then the XDG directories and the default
+/// instance; has no effect once any of the paths below has been used, fails if the instance
+/// name picked isn't valid
+pub fn init_dirs(
+    runtime: Option<PathBuf>,
+    state: Option<PathBuf>,
+    instance: Option<String>,
+    config: &Config,
+) -> Result<(), String> {
+    let _ = DIRS.set(resolve_dirs(runtime, state, instance, config)?);
+    Ok(())
+}
+
+/// instance names become directory names, so keep them to something safe for that
+pub fn valid_instance(name: &str) -> Result<(), String> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if !name.is_empty() && name.chars().all(allowed) {
+        Ok(())
+    } else {
+        Err(format!(
+            "instance name {:?} may only contain letters, digits, '-' and '_'",
+            name
+        ))
+    }
+}
+
+fn resolve_dirs(
+    runtime: Option<PathBuf>,
+    state: Option<PathBuf>,
+    instance: Option<String>,
+    config: &Config,
+) -> Result<Dirs, String> {
+    let home = || env::var_os("HOME").map(PathBuf::from);
+    let runtime = runtime
+        .or_else(|| env::var_os("CALTRAIND_RUNTIME_DIR").map(PathBuf::from))
+        .or_else(|| config.runtime_dir.clone())
+        .or_else(|| env::var_os("XDG_RUNTIME_DIR").map(|dir| Path::new(&dir).join("caltraind")))
+        .unwrap_or_else(|| env::temp_dir().join(format!("caltraind-{}", getuid())));
+    let state = state
+        .or_else(|| env::var_os("CALTRAIND_STATE_DIR").map(PathBuf::from))
+        .or_else(|| config.state_dir.clone())
+        .or_else(|| env::var_os("XDG_STATE_HOME").map(|dir| Path::new(&dir).join("caltraind")))
+        .or_else(|| home().map(|home| home.join(".local/state/caltraind")))
+        .unwrap_or_else(|| runtime.join("state"));
+    let instance = instance
+        .or_else(|| env::var("CALTRAIND_INSTANCE").ok())
+        .or_else(|| config.instance.clone())
+        .unwrap_or_else(|| DEFAULT_INSTANCE.to_string());
+    valid_instance(&instance)?;
+    Ok(Dirs {
+        runtime,
+        state,
+        instance,
+    })
+}
+
+pub fn instance() -> &'static str {
+    &dirs().instance
+}
+
+/// names and runtime directories of every instance that has been started
+pub fn instances() -> io::Result<Vec<(String, PathBuf)>> {
+    let mut instances = vec![];
+    for entry in read_dir(RUNTIME_BASE.as_path())? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                instances.push((name.to_string(), entry.path()));
+            }
+        }
+    }
+    instances.sort();
+    Ok(instances)
+}
+
+/// creates `path` readable only by the current user, tightening its permissions if it already
+/// exists and refusing to use it if someone else owns it
+pub fn private_dir(path: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(path)?;
+    let metadata = metadata(path)?;
+    if metadata.uid() != getuid().as_raw() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} belongs to another user", path.display()),
+        ));
+    }
+    if metadata.permissions().mode() & 0o077 != 0 {
+        set_permissions(path, Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// writes to a temporary file first so a crash never leaves half a file behind
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    write(&tmp, serde_json::to_string(value)?)?;
+    rename(tmp, path)
+}
+
+fn prepared(path: &Path) -> PathBuf {
+    private_dir(path).unwrap_or_else(|e| panic!("error creating {}: {}", path.display(), e));
+    path.to_path_buf()
+}
+
+/// holds a directory per instance, `$XDG_RUNTIME_DIR/caltraind` by default
+pub static RUNTIME_BASE: Lazy<PathBuf> = Lazy::new(|| prepared(&dirs().runtime));
+/// holds a directory per instance, `$XDG_STATE_HOME/caltraind` by default
+pub static STATE_BASE: Lazy<PathBuf> = Lazy::new(|| prepared(&dirs().state));
+
+/// this instance's socket and pid file
+pub static RUNTIME_PATH: Lazy<PathBuf> = Lazy::new(|| prepared(&RUNTIME_BASE.join(instance())));
+/// this instance's logs and saved data
+pub static STATE_PATH: Lazy<PathBuf> = Lazy::new(|| prepared(&STATE_BASE.join(instance())));
+
+pub static PID_PATH: Lazy<PathBuf> = Lazy::new(|| RUNTIME_PATH.join(PID_FILE));
+pub static CMDLINE_PATH: Lazy<PathBuf> = Lazy::new(|| RUNTIME_PATH.join(CMDLINE_FILE));
+pub static SOCKET_PATH: Lazy<PathBuf> = Lazy::new(|| RUNTIME_PATH.join("socket"));
+pub static STDOUT_PATH: Lazy<PathBuf> = Lazy::new(|| STATE_PATH.join("out.log"));
+pub static STDERR_PATH: Lazy<PathBuf> = Lazy::new(|| STATE_PATH.join("err.log"));
+pub static LOG_PATH: Lazy<PathBuf> = Lazy::new(|| STATE_PATH.join("caltraind.log"));
+pub static DATA_PATH: Lazy<PathBuf> = Lazy::new(|| prepared(&STATE_PATH.join("data")));
+
+pub static CONFIG_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("/etc"))
+        .join("caltraind")
 });
+pub static CONFIG_PATH: Lazy<PathBuf> = Lazy::new(|| CONFIG_DIR.join("config.yaml"));
+pub static LOCATION_PATH: Lazy<PathBuf> = Lazy::new(|| CONFIG_DIR.join("location"));
+
+/// settings read from yaml, keys match the long command line flags and anything given on the
+/// command line wins
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// a station name, or "auto" to use the station closest to the current location
+    pub station: Option<String>,
+    pub destination: Option<String>,
+    pub direction: Option<String>,
+    pub types: Option<Vec<String>>,
+    pub notify_at: Option<Vec<u16>>,
+    pub notify_after: Option<String>,
+    pub location_file: Option<PathBuf>,
+    pub travel_time: Option<u16>,
+    pub travel_mode: Option<String>,
+    pub buffer: Option<u16>,
+    pub return_after: Option<String>,
+    pub last_train: Option<String>,
+    pub last_train_warning: Option<u16>,
+    pub countdown: Option<bool>,
+    pub foreground: Option<bool>,
+    /// `text` or `json`
+    pub log_format: Option<String>,
+    /// megabytes the log grows to before it's rotated
+    pub log_max_size: Option<u64>,
+    /// rotated logs to keep
+    pub log_keep: Option<usize>,
+    /// tcp address to serve prometheus metrics on, eg. 127.0.0.1:9898
+    pub metrics_addr: Option<String>,
+    pub instance: Option<String>,
+    pub runtime_dir: Option<PathBuf>,
+    pub state_dir: Option<PathBuf>,
+    pub templates: TemplateConfig,
+    pub return_templates: TemplateConfig,
+    /// urgency and icon keyed by train type
+    pub styles: BTreeMap<String, StyleConfig>,
+}
+
+/// notification wording for each sink, see `caltraind::template` for the syntax
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplateConfig {
+    pub summary: Option<String>,
+    pub body: Option<String>,
+    pub log: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    pub urgency: Option<String>,
+    pub icon: Option<String>,
+}
 
-pub static PID_PATH: Lazy<PathBuf> =
-    Lazy::new(|| Path::new(CALTRAIND_PATH.as_os_str()).join("pid"));
-pub static SOCKET_PATH: Lazy<PathBuf> =
-    Lazy::new(|| Path::new(CALTRAIND_PATH.as_os_str()).join("socket"));
-pub static STDOUT_PATH: Lazy<PathBuf> =
-    Lazy::new(|| Path::new(CALTRAIND_PATH.as_os_str()).join("out.log"));
-pub static STDERR_PATH: Lazy<PathBuf> =
-    Lazy::new(|| Path::new(CALTRAIND_PATH.as_os_str()).join("err.log"));
+impl Config {
+    /// reads `path` if given, otherwise the default config file if there is one
+    pub fn load(path: Option<&Path>) -> Result<Config, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path,
+            None if CONFIG_PATH.exists() => CONFIG_PATH.as_path(),
+            None => return Ok(Config::default()),
+        };
+        let text = read_to_string(path)
+            .map_err(|e| format!("error reading config {}: {}", path.display(), e))?;
+        serde_yaml::from_str(&text)
+            .map_err(|e| format!("error parsing config {}: {}", path.display(), e).into())
+    }
+}
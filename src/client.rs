@@ -0,0 +1,193 @@
+use std::fs::read_to_string;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+
+use chrono::DateTime;
+
+use crate::caltrain_status::{Direction, IncomingTrain};
+use crate::cfg::{instances, CMDLINE_FILE, SOCKET_PATH};
+use crate::daemon::health::Health;
+use crate::daemon::running_pid;
+use crate::daemon::tracker::{Arrival, Snapshot};
+
+/// makes a request against the control socket of a running daemon, returning the http status
+/// code and the response body
+pub fn request(method: &str, path: &str) -> io::Result<(u16, String)> {
+    let mut stream = UnixStream::connect(SOCKET_PATH.as_path())?;
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: caltraind\r\nConnection: close\r\n\r\n",
+        method, path
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed response from daemon");
+    let split = response.find("\r\n\r\n").ok_or_else(invalid)?;
+    let code = response[..split]
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(invalid)?;
+    Ok((code, response[split + 4..].to_string()))
+}
+
+/// prints what the daemon last saw, returns the process exit code
+pub fn status() -> i32 {
+    let body = match request("GET", "/status") {
+        Ok((200, body)) => body,
+        Ok((code, body)) => {
+            eprintln!("daemon responded with {}: {}", code, body);
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("unable to reach caltraind, is it running? ({})", e);
+            return 1;
+        }
+    };
+    let snapshot: Snapshot = match serde_json::from_str(&body) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("invalid status from daemon: {}", e);
+            return 1;
+        }
+    };
+
+    println!(
+        "station: {} (zone {})",
+        snapshot.station,
+        snapshot.station.info().zone
+    );
+    println!(
+        "last update: {}",
+        snapshot
+            .last_update
+            .as_ref()
+            .map_or("never", String::as_str)
+    );
+    if let Some(destination) = snapshot.destination {
+        println!("destination: {} ({:?})", destination, snapshot.direction);
+    }
+    if let Some(status) = &snapshot.status {
+        let (northbound, southbound) = status.get_status();
+        let no_arrivals = vec![];
+        let arrivals = |direction| {
+            if direction == snapshot.direction {
+                &snapshot.arrivals
+            } else {
+                &no_arrivals
+            }
+        };
+        print_trains("northbound", northbound, arrivals(Direction::Northbound));
+        print_trains("southbound", southbound, arrivals(Direction::Southbound));
+    }
+    if let Some(error) = &snapshot.last_error {
+        println!(
+            "last error [{}] at {}: {}",
+            error.kind, error.at, error.message
+        );
+    }
+
+    match &snapshot.last_error {
+        Some(error) if snapshot.failing => error.exit_code,
+        _ => 0,
+    }
+}
+
+/// prints each health check the daemon ran, returns the process exit code, non-zero if any
+/// failed or the daemon can't be reached
+pub fn health() -> i32 {
+    let body = match request("GET", "/health") {
+        Ok((200, body)) | Ok((503, body)) => body,
+        Ok((code, body)) => {
+            eprintln!("daemon responded with {}: {}", code, body);
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("unable to reach caltraind, is it running? ({})", e);
+            return 1;
+        }
+    };
+    let health: Health = match serde_json::from_str(&body) {
+        Ok(health) => health,
+        Err(e) => {
+            eprintln!("invalid health from daemon: {}", e);
+            return 1;
+        }
+    };
+    for check in &health.checks {
+        let state = if check.ok { "ok" } else { "FAIL" };
+        println!("{:<4} {}: {}", state, check.name, check.detail);
+    }
+    if health.healthy {
+        0
+    } else {
+        let failing: Vec<&str> = health
+            .checks
+            .iter()
+            .filter(|check| !check.ok)
+            .map(|check| check.name.as_str())
+            .collect();
+        eprintln!("unhealthy: {}", failing.join(", "));
+        1
+    }
+}
+
+/// prints every instance, whether it's running and how it was started, returns the process
+/// exit code
+pub fn list() -> i32 {
+    let instances = match instances() {
+        Ok(instances) => instances,
+        Err(e) => {
+            eprintln!("unable to list instances: {}", e);
+            return 1;
+        }
+    };
+    for (name, runtime_dir) in instances {
+        let state = running_pid(&runtime_dir)
+            .map_or_else(|| "stopped".to_string(), |pid| format!("pid {}", pid));
+        let cmdline = read_to_string(runtime_dir.join(CMDLINE_FILE)).unwrap_or_default();
+        println!("{:<16} {:<12} {}", name, state, cmdline.trim());
+    }
+    0
+}
+
+/// passes a snooze, skip or take action for `train` on to the daemon, returns the process exit
+/// code
+pub fn action(train: u16, action: &str) -> i32 {
+    match request("POST", &format!("/trains/{}/{}", train, action)) {
+        Ok((202, _)) => 0,
+        Ok((code, body)) => {
+            eprintln!("daemon responded with {}: {}", code, body);
+            1
+        }
+        Err(e) => {
+            eprintln!("unable to reach caltraind, is it running? ({})", e);
+            1
+        }
+    }
+}
+
+fn print_trains(direction: &str, trains: &[IncomingTrain], arrivals: &[Arrival]) {
+    println!("{}:", direction);
+    for train in trains {
+        let arrival = arrivals
+            .iter()
+            .find(|arrival| arrival.train == train.get_id())
+            .and_then(|arrival| DateTime::parse_from_rfc3339(&arrival.at).ok())
+            .map(|at| {
+                format!(
+                    ", arriving around {}",
+                    at.format("%l:%M%p").to_string().trim()
+                )
+            })
+            .unwrap_or_default();
+        println!(
+            "  {} {} departing in {} min{}",
+            train.get_train_type(),
+            train.get_id(),
+            train.get_min_till_departure(),
+            arrival
+        );
+    }
+}
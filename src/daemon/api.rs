@@ -0,0 +1,59 @@
+use actix::Addr;
+use actix_web::{error, web, HttpResponse};
+use futures::compat::{Compat, Future01CompatExt};
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+
+use crate::daemon::health::{self, HealthPolicy};
+use crate::daemon::metrics;
+use crate::daemon::notifier::{Action, Choose, Notifier, TrainAction};
+use crate::daemon::tracker::{GetSnapshot, StatusTracker};
+
+type Response = Compat<LocalBoxFuture<'static, Result<HttpResponse, actix_web::Error>>>;
+
+/// GET /status, the last status fetched from caltrain and the last error seen doing so
+pub fn status(tracker: web::Data<Addr<StatusTracker>>) -> Response {
+    get_status(tracker.get_ref().clone()).boxed_local().compat()
+}
+
+async fn get_status(tracker: Addr<StatusTracker>) -> Result<HttpResponse, actix_web::Error> {
+    let snapshot = tracker
+        .send(GetSnapshot)
+        .compat()
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(snapshot))
+}
+
+/// GET /health, 200 when fetching, parsing and every sink look fine, 503 with the failing checks
+/// otherwise
+pub fn health(policy: web::Data<HealthPolicy>) -> HttpResponse {
+    let health = health::check(&policy);
+    if health.healthy {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+/// GET /metrics, fetch, parse and notification counts in the prometheus text format
+pub fn metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render())
+}
+
+/// POST /trains/{train}/{action}, the same snooze, skip and take actions notifications offer
+pub fn train_action(
+    notifier: web::Data<Addr<Notifier>>,
+    path: web::Path<(u16, String)>,
+) -> HttpResponse {
+    let (train, action) = path.into_inner();
+    match action.parse::<Action>() {
+        Ok(action) => {
+            notifier.do_send(Choose(TrainAction { train, action }));
+            HttpResponse::Accepted().finish()
+        }
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
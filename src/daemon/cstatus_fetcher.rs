@@ -1,77 +1,440 @@
-use std::time::Duration;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use actix::prelude::*;
-use actix_broker::{BrokerIssue, SystemBroker};
+use actix_broker::{BrokerIssue, BrokerSubscribe, SystemBroker};
 use actix_web::client::Client;
-use futures::{compat::Future01CompatExt, FutureExt, TryFutureExt};
+use chrono::{DateTime, Local};
+use futures::{compat::Future01CompatExt, future::join_all, FutureExt, TryFutureExt};
+use log::{debug, error, trace, warn};
 
-use crate::caltrain_status::CaltrainStatus;
+use crate::caltrain_status::{CaltrainStatus, Direction, Error};
+use crate::cfg::DATA_PATH;
+use crate::daemon::metrics;
+use crate::daemon::notifier::{Action, TrainAction, SNOOZE_MINUTES};
+use crate::daemon::profile::Profile;
+use crate::daemon::replay;
+use crate::daemon::shutdown::ShuttingDown;
 use crate::station::Station;
 
+/// upper bound on how long to wait between attempts while caltrain is failing
+const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Clone, Debug)]
+pub struct FetchPolicy {
+    pub min_refresh_rate: Duration,
+    pub max_refresh_rate: Duration,
+    pub timeout: Duration,
+    pub stale_after: Duration,
+    /// directory to save every fetched page to, for later replay
+    pub record_to: Option<PathBuf>,
+}
+
+impl FetchPolicy {
+    /// the longest the fetcher can go between attempts, backing off while caltrain fails included
+    pub fn longest_gap(&self) -> Duration {
+        self.max_refresh_rate.max(MAX_BACKOFF) + self.timeout
+    }
+}
+
+/// issued every time a status update succeeds
+#[derive(Clone, Debug)]
+pub struct StationStatus {
+    pub station: Station,
+    pub status: CaltrainStatus,
+}
+
+impl Message for StationStatus {
+    type Result = ();
+}
+
+/// issued every time a status update fails
+#[derive(Clone, Debug)]
+pub struct FetchFailed {
+    pub station: Station,
+    pub error: Error,
+    pub at: DateTime<Local>,
+}
+
+impl Message for FetchFailed {
+    type Result = ();
+}
+
+/// issued once when the caltrain page stops matching what the parser expects, until a page
+/// parses cleanly again
+#[derive(Clone, Debug)]
+pub struct MarkupChanged {
+    pub station: Station,
+    pub error: Error,
+    pub saved_to: Option<PathBuf>,
+}
+
+impl Message for MarkupChanged {
+    type Result = ();
+}
+
+/// issued once per outage when no status update has succeeded for `stale_after`
+#[derive(Clone, Debug)]
+pub struct StatusStale {
+    pub since_last_update: Duration,
+}
+
+impl Message for StatusStale {
+    type Result = ();
+}
+
 pub struct CStatusFetcher {
     station: Station,
-    duration: Duration,
+    policy: FetchPolicy,
+    profiles: Vec<Profile>,
+    consecutive_failures: u32,
+    last_success: Instant,
+    stale_reported: bool,
+    markup_reported: bool,
+    timeout_handle: Option<SpawnHandle>,
+    /// the next poll and when it's due, while one is waiting
+    poll_handle: Option<(SpawnHandle, Instant)>,
+    /// when snoozes end, each gets a poll of its own so the train comes back on time
+    snoozes: Vec<Instant>,
 }
 
 impl CStatusFetcher {
-    pub fn new(station: Station, duration: Duration) -> Self {
-        CStatusFetcher { station, duration }
+    pub fn new(station: Station, policy: FetchPolicy, profiles: Vec<Profile>) -> Self {
+        CStatusFetcher {
+            station,
+            policy,
+            profiles,
+            consecutive_failures: 0,
+            last_success: Instant::now(),
+            stale_reported: false,
+            markup_reported: false,
+            timeout_handle: None,
+            poll_handle: None,
+            snoozes: vec![],
+        }
     }
 
-    async fn update_status(station: Station) -> Result<CaltrainStatus, String> {
-        let result = Client::default()
-            .get(station.get_url())
-            .send()
-            .compat()
-            .await;
-        let mut resp = match result {
-            Ok(resp) => resp,
-            Err(e) => {
-                return Err(format!("error making request to caltrain: {}", e));
+    fn run_status_update(&mut self, ctx: &mut <CStatusFetcher as Actor>::Context) {
+        debug!("fetching status for {}", self.station);
+        let started = Instant::now();
+        let status_update_future = fetch_page(self.station, self.policy.timeout)
+            .unit_error()
+            .boxed_local()
+            .compat();
+        let wrapped = actix::fut::wrap_future::<_, Self>(status_update_future);
+        let emitted = wrapped.map(move |result, actor, ctx| {
+            if let Some(handle) = actor.timeout_handle.take() {
+                ctx.cancel_future(handle);
             }
-        };
-        let bytes = match resp.body().compat().await {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                return Err(format!("invalid payload from caltrain: {}", e));
+            if let (Ok(page), Some(dir)) = (&result, &actor.policy.record_to) {
+                if let Err(e) = replay::record(dir, actor.station, page) {
+                    error!("error recording caltrain page: {}", e);
+                }
             }
-        };
-        let text = match String::from_utf8(bytes.to_vec()) {
-            Ok(text) => text,
+            let parsed = result.map(|page| (CaltrainStatus::from_html(&page), page));
+            let (outcome, parse_result) = match &parsed {
+                Ok((Ok(_), _)) => ("ok", Some("ok")),
+                Ok((Err(e), _)) => (e.kind(), Some(e.kind())),
+                Err(e) => (e.kind(), None),
+            };
+            metrics::fetched(actor.station, started.elapsed(), outcome);
+            if let Some(parse_result) = parse_result {
+                metrics::parsed(actor.station, parse_result);
+            }
+            match parsed {
+                Ok((Ok(cstatus), _)) => actor.on_success(cstatus, ctx),
+                Ok((Err(e), page)) => {
+                    if e.is_markup_change() {
+                        actor.on_markup_change(&e, &page);
+                    }
+                    actor.on_failure(e, ctx)
+                }
+                Err(e) => actor.on_failure(e, ctx),
+            }
+        });
+        let request_handle = ctx.spawn(emitted);
+
+        // the client timeout only covers the response head, so guard the whole exchange
+        let timeout = self.policy.timeout;
+        self.timeout_handle = Some(ctx.run_later(timeout, move |actor, ctx| {
+            actor.timeout_handle = None;
+            if ctx.cancel_future(request_handle) {
+                let error = Error::Timeout(timeout);
+                metrics::fetched(actor.station, timeout, error.kind());
+                actor.on_failure(error, ctx);
+            }
+        }));
+    }
+
+    fn on_success(
+        &mut self,
+        cstatus: CaltrainStatus,
+        ctx: &mut <CStatusFetcher as Actor>::Context,
+    ) {
+        self.consecutive_failures = 0;
+        self.last_success = Instant::now();
+        self.stale_reported = false;
+        self.markup_reported = false;
+        let delay = self.next_poll(&cstatus);
+        let (northbound, southbound) = cstatus.get_status();
+        debug!(
+            "{}: {} northbound and {} southbound trains, next poll in {}s",
+            self.station,
+            northbound.len(),
+            southbound.len(),
+            delay.as_secs()
+        );
+        trace!("{}: {:?}", self.station, cstatus);
+        metrics::status_received(self.station, &cstatus);
+        self.issue_async::<SystemBroker, _>(StationStatus {
+            station: self.station,
+            status: cstatus,
+        });
+        self.schedule_next(delay, ctx);
+    }
+
+    fn on_failure(&mut self, error: Error, ctx: &mut <CStatusFetcher as Actor>::Context) {
+        self.consecutive_failures += 1;
+        let since_last_update = self.last_success.elapsed();
+        warn!(
+            "{}: fetch failed [{}]: {} (attempt {}, last update {}s ago)",
+            self.station,
+            error.kind(),
+            error,
+            self.consecutive_failures,
+            since_last_update.as_secs()
+        );
+        self.issue_async::<SystemBroker, _>(FetchFailed {
+            station: self.station,
+            error,
+            at: Local::now(),
+        });
+        if !self.stale_reported && since_last_update >= self.policy.stale_after {
+            self.stale_reported = true;
+            self.issue_async::<SystemBroker, _>(StatusStale { since_last_update });
+        }
+        let delay = backoff(self.policy.min_refresh_rate, self.consecutive_failures);
+        self.schedule_next(delay, ctx);
+    }
+
+    fn on_markup_change(&mut self, error: &Error, page: &str) {
+        if self.markup_reported {
+            return;
+        }
+        self.markup_reported = true;
+        let saved_to = match save_page(self.station, page) {
+            Ok(path) => Some(path),
             Err(e) => {
-                return Err(format!(
-                    "error while parsing resposne from caltrain as utf-8: {}",
-                    e
-                ));
+                error!("error saving unparseable caltrain page: {}", e);
+                None
             }
         };
-        match CaltrainStatus::from_html(text) {
-            Ok(cstatus) => Ok(cstatus),
-            Err(e) => Err(format!("error parsing caltrain xml: {}", e)),
-        }
+        error!(
+            "caltrain page for {:?} no longer matches the expected layout: {}{}",
+            self.station,
+            error,
+            saved_to
+                .as_ref()
+                .map(|p| format!(", page saved to {}", p.display()))
+                .unwrap_or_default()
+        );
+        self.issue_async::<SystemBroker, _>(MarkupChanged {
+            station: self.station,
+            error: error.clone(),
+            saved_to,
+        });
     }
 
-    fn run_status_update(&mut self, ctx: &mut <CStatusFetcher as Actor>::Context) {
-        let status_update_future = CStatusFetcher::update_status(self.station)
+    /// polls again just before the closest train of interest crosses one of its notification
+    /// offsets, so the notification goes out within `min_refresh_rate` of the threshold, or at
+    /// `min_refresh_rate` while a countdown notification is up so it keeps ticking
+    fn next_poll(&self, cstatus: &CaltrainStatus) -> Duration {
+        let (northbound, southbound) = cstatus.get_status();
+        let min_till_threshold = self
+            .profiles
+            .iter()
+            .flat_map(|profile| {
+                let trains = match profile.direction {
+                    Direction::Northbound => northbound,
+                    Direction::Southbound => southbound,
+                };
+                let lead = profile.travel.lead_minutes();
+                trains
+                    .iter()
+                    .filter(move |train| profile.train_types.contains(&train.get_train_type()))
+                    .filter_map(move |train| {
+                        let departs = train.get_min_till_departure();
+                        let thresholds = profile
+                            .notify_at
+                            .iter()
+                            .map(move |n| n.saturating_add(lead));
+                        // past a threshold, so the notifier is counting this train down until
+                        // it leaves
+                        if profile.countdown && thresholds.clone().any(|t| departs < t) {
+                            return Some(0);
+                        }
+                        thresholds.filter_map(|t| departs.checked_sub(t)).min()
+                    })
+            })
+            .min();
+        poll_delay(min_till_threshold, &self.policy)
+    }
+
+    fn schedule_next(&mut self, delay: Duration, ctx: &mut <CStatusFetcher as Actor>::Context) {
+        let now = Instant::now();
+        self.snoozes.retain(|until| *until > now);
+        let delay = self
+            .snoozes
+            .iter()
+            .map(|until| *until - now)
+            .fold(delay, Duration::min);
+        let handle = ctx.run_later(
+            delay,
+            |csf: &mut Self, ctx: &mut <CStatusFetcher as Actor>::Context| {
+                csf.poll_handle = None;
+                csf.run_status_update(ctx)
+            },
+        );
+        self.poll_handle = Some((handle, now + delay));
+    }
+}
+
+async fn fetch_page(station: Station, timeout: Duration) -> Result<String, Error> {
+    let mut resp = Client::default()
+        .get(station.get_url())
+        .timeout(timeout)
+        .send()
+        .compat()
+        .await
+        .map_err(|e| Error::Transport(e.to_string()))?;
+    if !resp.status().is_success() {
+        return Err(Error::HttpStatus(resp.status().as_u16()));
+    }
+    let bytes = resp
+        .body()
+        .compat()
+        .await
+        .map_err(|e| Error::Transport(e.to_string()))?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+async fn fetch_statuses(
+    stations: Vec<Station>,
+    timeout: Duration,
+) -> Vec<(Station, Result<CaltrainStatus, Error>)> {
+    let pages = join_all(stations.iter().map(|station| fetch_page(*station, timeout))).await;
+    stations
+        .into_iter()
+        .zip(pages)
+        .map(|(station, page)| (station, page.and_then(CaltrainStatus::from_html)))
+        .collect()
+}
+
+/// fetches the status of each station once, side by side, outside of a running daemon
+pub fn fetch_once(
+    stations: Vec<Station>,
+    timeout: Duration,
+) -> io::Result<Vec<(Station, Result<CaltrainStatus, Error>)>> {
+    let mut sys = System::new("caltraind-fetch");
+    sys.block_on(
+        fetch_statuses(stations, timeout)
             .unit_error()
             .boxed_local()
-            .compat();
-        let wrapped = actix::fut::wrap_future::<_, Self>(status_update_future);
-        let emitted = wrapped.map(|result, actor, _| match result {
-            Ok(cstatus) => actor.issue_async::<SystemBroker, _>(cstatus),
-            Err(msg) => eprintln!("{}", msg),
-        });
-        ctx.spawn(emitted);
+            .compat(),
+    )
+    .map_err(|()| io::Error::new(io::ErrorKind::Other, "fetch was interrupted"))
+}
+
+fn save_page(station: Station, page: &str) -> io::Result<PathBuf> {
+    let path = DATA_PATH.join(format!(
+        "unparsed-{:?}-{}.html",
+        station,
+        Local::now().format("%Y%m%dT%H%M%S")
+    ));
+    std::fs::write(&path, page)?;
+    Ok(path)
+}
+
+fn poll_delay(min_till_threshold: Option<u16>, policy: &FetchPolicy) -> Duration {
+    match min_till_threshold {
+        // departure times are whole minutes, so the last minute before a threshold could
+        // be crossed at any point and gets polled at the fastest rate
+        Some(minutes) => Duration::from_secs(60 * u64::from(minutes.saturating_sub(1)))
+            .max(policy.min_refresh_rate)
+            .min(policy.max_refresh_rate),
+        None => policy.max_refresh_rate,
     }
 }
 
+/// doubles the refresh rate for every consecutive failure, capped at `MAX_BACKOFF`, then
+/// picks a point in the upper half of that window so retries don't synchronize
+fn backoff(refresh_rate: Duration, consecutive_failures: u32) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+    let window = refresh_rate
+        .checked_mul(1u32 << exponent)
+        .map_or(MAX_BACKOFF, |d| d.min(MAX_BACKOFF));
+    let fraction = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| f64::from(d.subsec_nanos()) / 1_000_000_000.0)
+        .unwrap_or(0.0);
+    window.mul_f64(0.5 + fraction / 2.0)
+}
+
 impl Actor for CStatusFetcher {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(self.duration, |csf: &mut Self, ctx: &mut Self::Context| {
-            csf.run_status_update(ctx)
-        });
+        self.subscribe_system_async::<ShuttingDown>(ctx);
+        self.subscribe_system_async::<TrainAction>(ctx);
+        self.last_success = Instant::now();
         self.run_status_update(ctx);
     }
 }
+
+impl Handler<TrainAction> for CStatusFetcher {
+    type Result = ();
+
+    fn handle(&mut self, chosen: TrainAction, ctx: &mut Self::Context) -> Self::Result {
+        if chosen.action != Action::Snooze {
+            return;
+        }
+        let until = Instant::now() + Duration::from_secs(60 * SNOOZE_MINUTES as u64);
+        self.snoozes.push(until);
+        // a fetch in flight schedules the next poll itself once it's done
+        if let Some((handle, due)) = self.poll_handle {
+            if due > until && ctx.cancel_future(handle) {
+                self.schedule_next(due - Instant::now(), ctx);
+            }
+        }
+    }
+}
+
+impl Handler<ShuttingDown> for CStatusFetcher {
+    type Result = ();
+
+    fn handle(&mut self, _: ShuttingDown, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn poll_delay_clamps_to_policy() {
+        let policy = FetchPolicy {
+            min_refresh_rate: Duration::from_secs(15),
+            max_refresh_rate: Duration::from_secs(300),
+            timeout: Duration::from_secs(15),
+            stale_after: Duration::from_secs(600),
+            record_to: None,
+        };
+        assert_eq!(poll_delay(None, &policy), Duration::from_secs(300));
+        assert_eq!(poll_delay(Some(0), &policy), Duration::from_secs(15));
+        assert_eq!(poll_delay(Some(1), &policy), Duration::from_secs(15));
+        assert_eq!(poll_delay(Some(3), &policy), Duration::from_secs(120));
+        assert_eq!(poll_delay(Some(90), &policy), Duration::from_secs(300));
+    }
+}
@@ -0,0 +1,195 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::daemon::metrics::{self, Activity};
+use crate::logging;
+use crate::station::Station;
+
+/// what counts as healthy for this daemon
+#[derive(Clone, Debug)]
+pub struct HealthPolicy {
+    pub stations: Vec<Station>,
+    /// longest gap between fetch attempts, `None` when replaying recordings
+    pub fetch_within: Option<Duration>,
+    /// longest time without a good status
+    pub stale_after: Option<Duration>,
+    /// when the daemon started, nothing having been fetched yet is fine for `fetch_within` after
+    pub started: Instant,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, result: Result<String, String>) -> Self {
+        let ok = result.is_ok();
+        Check {
+            name: name.into(),
+            ok,
+            detail: result.unwrap_or_else(|e| e),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Health {
+    pub healthy: bool,
+    pub checks: Vec<Check>,
+}
+
+fn ago(at: Instant, now: Instant) -> u64 {
+    now.duration_since(at).as_secs()
+}
+
+/// whether `station` was fetched recently, parsed cleanly last time and has a recent status
+fn station_checks(
+    station: Station,
+    activity: &Activity,
+    policy: &HealthPolicy,
+    now: Instant,
+) -> Vec<Check> {
+    // the first fetch may still be on its way, as when systemd runs `caltraind health` right
+    // after the daemon reports it's ready
+    let starting = policy.fetch_within.map_or(false, |fetch_within| {
+        now.duration_since(policy.started) <= fetch_within
+    });
+    let not_yet = |what: &str| {
+        if starting {
+            Ok(format!("starting, {} yet", what))
+        } else {
+            Err(format!("{} yet", what))
+        }
+    };
+    let mut checks = vec![];
+    if let Some(fetch_within) = policy.fetch_within {
+        checks.push(Check::new(
+            format!("fetch {}", station),
+            match activity.last_fetch {
+                Some(at) if now.duration_since(at) <= fetch_within => {
+                    Ok(format!("last attempt {}s ago", ago(at, now)))
+                }
+                Some(at) => Err(format!("no attempt in {}s", ago(at, now))),
+                None => not_yet("no attempt"),
+            },
+        ));
+    }
+    checks.push(Check::new(
+        format!("parse {}", station),
+        match activity.last_parse {
+            Some("ok") => Ok("last page parsed".to_string()),
+            Some(kind) => Err(format!("last page failed to parse [{}]", kind)),
+            None => not_yet("no page parsed"),
+        },
+    ));
+    if let Some(stale_after) = policy.stale_after {
+        checks.push(Check::new(
+            format!("status {}", station),
+            match activity.last_status {
+                Some(at) if now.duration_since(at) <= stale_after => {
+                    Ok(format!("updated {}s ago", ago(at, now)))
+                }
+                Some(at) => Err(format!("stale, updated {}s ago", ago(at, now))),
+                None => not_yet("no status"),
+            },
+        ));
+    }
+    checks
+}
+
+/// whether the notification server is answering on the session bus
+fn desktop_check() -> Check {
+    Check::new(
+        "sink desktop",
+        notify_rust::get_server_information()
+            .map(|server| format!("{} {}", server.name, server.version))
+            .map_err(|e| format!("notification server unreachable: {}", e)),
+    )
+}
+
+fn log_check() -> Check {
+    Check::new(
+        "sink log",
+        if logging::failing() {
+            Err("writing the log failed".to_string())
+        } else {
+            Ok("writable".to_string())
+        },
+    )
+}
+
+/// runs every check, talking to the notification server so it can block briefly
+pub fn check(policy: &HealthPolicy) -> Health {
+    let now = Instant::now();
+    let mut checks: Vec<Check> = policy
+        .stations
+        .iter()
+        .flat_map(|station| station_checks(*station, &metrics::activity(*station), policy, now))
+        .collect();
+    checks.push(desktop_check());
+    checks.push(log_check());
+    Health {
+        healthy: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn station_checks_report_reasons() {
+        let start = Instant::now();
+        let policy = HealthPolicy {
+            stations: vec![Station::PaloAlto],
+            fetch_within: Some(Duration::from_secs(60)),
+            stale_after: Some(Duration::from_secs(600)),
+            started: start,
+        };
+        let activity = Activity {
+            last_fetch: Some(start),
+            last_parse: Some("table_not_found"),
+            last_status: Some(start),
+        };
+
+        let checks = station_checks(Station::PaloAlto, &activity, &policy, start);
+        let failing: Vec<&str> = checks
+            .iter()
+            .filter(|check| !check.ok)
+            .map(|check| check.detail.as_str())
+            .collect();
+        assert_eq!(failing, ["last page failed to parse [table_not_found]"]);
+
+        let later = start + Duration::from_secs(120);
+        let checks = station_checks(Station::PaloAlto, &activity, &policy, later);
+        assert_eq!(checks[0].detail, "no attempt in 120s");
+        assert!(!checks[0].ok);
+        assert!(checks[2].ok);
+    }
+
+    #[test]
+    fn station_checks_allow_startup() {
+        let start = Instant::now();
+        let policy = HealthPolicy {
+            stations: vec![Station::PaloAlto],
+            fetch_within: Some(Duration::from_secs(60)),
+            stale_after: Some(Duration::from_secs(600)),
+            started: start,
+        };
+        let nothing = Activity::default();
+
+        let checks = station_checks(Station::PaloAlto, &nothing, &policy, start);
+        assert!(checks.iter().all(|check| check.ok));
+        assert_eq!(checks[0].detail, "starting, no attempt yet");
+
+        let later = start + Duration::from_secs(61);
+        let checks = station_checks(Station::PaloAlto, &nothing, &policy, later);
+        assert!(checks.iter().all(|check| !check.ok));
+        assert_eq!(checks[1].detail, "no page parsed yet");
+    }
+}
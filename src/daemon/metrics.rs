@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+use crate::caltrain_status::{CaltrainStatus, Direction};
+use crate::station::Station;
+
+const BUCKET_COUNT: usize = 9;
+
+/// upper bounds of the fetch latency buckets, in seconds
+const FETCH_BUCKETS: [f64; BUCKET_COUNT] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 15.0, 30.0];
+
+#[derive(Default)]
+struct Histogram {
+    /// cumulative, one per `FETCH_BUCKETS` entry
+    buckets: [u64; BUCKET_COUNT],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bound, bucket) in FETCH_BUCKETS.iter().zip(self.buckets.iter_mut()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// when a station was last fetched and how that went, for health checks
+#[derive(Clone, Debug, Default)]
+pub struct Activity {
+    pub last_fetch: Option<Instant>,
+    /// `ok` or the error kind
+    pub last_parse: Option<&'static str>,
+    pub last_status: Option<Instant>,
+}
+
+/// everything `/metrics` reports, shared by the fetchers, the notifier and the http workers
+#[derive(Default)]
+struct Metrics {
+    fetch_seconds: BTreeMap<Station, Histogram>,
+    /// by station and `ok` or the error kind
+    fetches: BTreeMap<(Station, &'static str), u64>,
+    /// by station and `ok` or the error kind, only for pages that were received
+    parses: BTreeMap<(Station, &'static str), u64>,
+    trains: BTreeMap<(Station, Direction), usize>,
+    activity: BTreeMap<Station, Activity>,
+    /// by sink and `ok` or `error`
+    notifications: BTreeMap<(&'static str, &'static str), u64>,
+}
+
+static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(Default::default);
+
+fn metrics() -> MutexGuard<'static, Metrics> {
+    match METRICS.lock() {
+        Ok(metrics) => metrics,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// records a fetch that took `elapsed` and ended with `outcome`, `ok` or the error kind
+pub fn fetched(station: Station, elapsed: Duration, outcome: &'static str) {
+    let mut metrics = metrics();
+    metrics
+        .fetch_seconds
+        .entry(station)
+        .or_default()
+        .observe(elapsed.as_secs_f64());
+    *metrics.fetches.entry((station, outcome)).or_insert(0) += 1;
+    metrics.activity.entry(station).or_default().last_fetch = Some(Instant::now());
+}
+
+/// records parsing a received page, `ok` or the error kind
+pub fn parsed(station: Station, result: &'static str) {
+    let mut metrics = metrics();
+    *metrics.parses.entry((station, result)).or_insert(0) += 1;
+    metrics.activity.entry(station).or_default().last_parse = Some(result);
+}
+
+pub fn status_received(station: Station, status: &CaltrainStatus) {
+    let (northbound, southbound) = status.get_status();
+    let mut metrics = metrics();
+    metrics
+        .trains
+        .insert((station, Direction::Northbound), northbound.len());
+    metrics
+        .trains
+        .insert((station, Direction::Southbound), southbound.len());
+    metrics.activity.entry(station).or_default().last_status = Some(Instant::now());
+}
+
+pub fn activity(station: Station) -> Activity {
+    metrics()
+        .activity
+        .get(&station)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// records a notification going out through `sink`, `desktop` or `log`
+pub fn notified(sink: &'static str, ok: bool) {
+    let result = if ok { "ok" } else { "error" };
+    *metrics().notifications.entry((sink, result)).or_insert(0) += 1;
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// everything recorded so far in the prometheus text format
+pub fn render() -> String {
+    let metrics = metrics();
+    let mut out = String::new();
+
+    header(
+        &mut out,
+        "caltraind_fetch_duration_seconds",
+        "histogram",
+        "time taken to fetch a caltrain status page",
+    );
+    for (station, histogram) in &metrics.fetch_seconds {
+        let station = label(&station.to_string());
+        for (bound, count) in FETCH_BUCKETS.iter().zip(histogram.buckets.iter()) {
+            let _ = writeln!(
+                out,
+                "caltraind_fetch_duration_seconds_bucket{{station=\"{}\",le=\"{}\"}} {}",
+                station, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "caltraind_fetch_duration_seconds_bucket{{station=\"{}\",le=\"+Inf\"}} {}",
+            station, histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "caltraind_fetch_duration_seconds_sum{{station=\"{}\"}} {}",
+            station, histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "caltraind_fetch_duration_seconds_count{{station=\"{}\"}} {}",
+            station, histogram.count
+        );
+    }
+
+    header(
+        &mut out,
+        "caltraind_fetches_total",
+        "counter",
+        "status fetches by outcome, ok or the error kind",
+    );
+    for ((station, outcome), count) in &metrics.fetches {
+        let _ = writeln!(
+            out,
+            "caltraind_fetches_total{{station=\"{}\",outcome=\"{}\"}} {}",
+            label(&station.to_string()),
+            outcome,
+            count
+        );
+    }
+
+    header(
+        &mut out,
+        "caltraind_parses_total",
+        "counter",
+        "received pages by parse result, ok or the error kind",
+    );
+    for ((station, result), count) in &metrics.parses {
+        let _ = writeln!(
+            out,
+            "caltraind_parses_total{{station=\"{}\",result=\"{}\"}} {}",
+            label(&station.to_string()),
+            result,
+            count
+        );
+    }
+
+    header(
+        &mut out,
+        "caltraind_trains",
+        "gauge",
+        "trains listed in the last good status",
+    );
+    for ((station, direction), count) in &metrics.trains {
+        let _ = writeln!(
+            out,
+            "caltraind_trains{{station=\"{}\",direction=\"{:?}\"}} {}",
+            label(&station.to_string()),
+            direction,
+            count
+        );
+    }
+
+    header(
+        &mut out,
+        "caltraind_last_status_age_seconds",
+        "gauge",
+        "seconds since the last good status",
+    );
+    for (station, activity) in &metrics.activity {
+        let at = match activity.last_status {
+            Some(at) => at,
+            None => continue,
+        };
+        let _ = writeln!(
+            out,
+            "caltraind_last_status_age_seconds{{station=\"{}\"}} {}",
+            label(&station.to_string()),
+            at.elapsed().as_secs()
+        );
+    }
+
+    header(
+        &mut out,
+        "caltraind_notifications_total",
+        "counter",
+        "notifications by sink and result",
+    );
+    for ((sink, result), count) in &metrics.notifications {
+        let _ = writeln!(
+            out,
+            "caltraind_notifications_total{{sink=\"{}\",result=\"{}\"}} {}",
+            sink, result, count
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.2);
+        histogram.observe(3.0);
+        histogram.observe(60.0);
+        assert_eq!(histogram.buckets, [0, 1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(histogram.count, 3);
+
+        fetched(Station::PaloAlto, Duration::from_millis(200), "ok");
+        let rendered = render();
+        assert!(rendered.contains(
+            "caltraind_fetch_duration_seconds_bucket{station=\"Palo Alto\",le=\"0.25\"} 1\n"
+        ));
+        assert!(
+            rendered.contains("caltraind_fetches_total{station=\"Palo Alto\",outcome=\"ok\"} 1\n")
+        );
+    }
+}
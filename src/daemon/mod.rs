@@ -1,74 +1,248 @@
-use std::collections::BTreeSet;
+use std::env;
 use std::error::Error;
-use std::fs::{read_to_string, File};
-use std::time::Duration;
+use std::fs::{remove_file, set_permissions, write, File, OpenOptions, Permissions};
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
 
 use actix::{Actor, System};
-use actix_web::{App, HttpServer};
+use actix_web::{web, App, HttpServer};
 use daemonize::Daemonize;
-use nix::errno::Errno;
-use nix::sys::signal;
-use nix::unistd::Pid;
+use log::{info, warn};
 
-use crate::caltrain_status::{Direction, TrainType};
-use crate::cfg::{CALTRAIND_PATH, PID_PATH, SOCKET_PATH, STDERR_PATH, STDOUT_PATH};
+use crate::cfg::{
+    instance, CMDLINE_PATH, PID_FILE, PID_PATH, RUNTIME_PATH, SOCKET_PATH, STDERR_PATH, STDOUT_PATH,
+};
 use crate::daemon::cstatus_fetcher::CStatusFetcher;
+use crate::daemon::health::HealthPolicy;
 use crate::daemon::notifier::Notifier;
-use crate::station::Station;
-use chrono::NaiveTime;
+use crate::daemon::replay::Replayer;
+use crate::daemon::return_trip::ReturnReminder;
+use crate::daemon::shutdown::Shutdown;
+use crate::daemon::systemd::Watchdog;
+use crate::daemon::tracker::StatusTracker;
 
+mod api;
 mod cstatus_fetcher;
+pub mod health;
+mod metrics;
+mod notified_state;
 mod notifier;
+mod pid_file;
+mod profile;
+mod replay;
+mod return_trip;
+mod shutdown;
+pub mod systemd;
+pub mod tracker;
 
-pub fn close_existing() {
-    let pid = match read_to_string(PID_PATH.as_path()) {
-        Ok(s) => s.parse::<i32>().expect("pid file did not contain pid"),
-        Err(_) => return,
-    };
-    match signal::kill(Pid::from_raw(pid), signal::SIGTERM) {
-        Ok(_) | Err(nix::Error::Sys(Errno::ESRCH)) => {
-            std::fs::remove_file(PID_PATH.as_path()).expect("error deleting pid file")
-        }
-        _ => (),
+pub use crate::daemon::cstatus_fetcher::{fetch_once, FetchPolicy};
+pub use crate::daemon::pid_file::Stopped;
+pub use crate::daemon::profile::{Profile, ReturnTrip, Style, Templates, Travel, TravelMode};
+
+/// how long `kill` waits after SIGTERM before sending SIGKILL, longer than the daemon gives
+/// itself to flush on the way out
+const KILL_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// where train status comes from
+pub enum StatusSource {
+    Live(FetchPolicy),
+    Replay { dir: PathBuf, speed: f64 },
+}
+
+/// stops this instance's daemon
+pub fn close_existing() -> io::Result<Stopped> {
+    close_instance(RUNTIME_PATH.as_path())
+}
+
+/// stops the daemon whose runtime directory is `runtime_dir`, killing it if it doesn't exit
+/// promptly
+pub fn close_instance(runtime_dir: &Path) -> io::Result<Stopped> {
+    pid_file::stop(&runtime_dir.join(PID_FILE), KILL_TIMEOUT, true)
+}
+
+/// asks the daemon whose runtime directory is `runtime_dir` to shut down and waits up to
+/// `timeout` for it to save its state and exit, without ever killing it
+pub fn stop_instance(runtime_dir: &Path, timeout: Duration) -> io::Result<Stopped> {
+    pid_file::stop(&runtime_dir.join(PID_FILE), timeout, false)
+}
+
+/// pid of the daemon whose runtime directory is `runtime_dir`, if it's still alive
+pub fn running_pid(runtime_dir: &Path) -> Option<i32> {
+    pid_file::running(&runtime_dir.join(PID_FILE)).unwrap_or(None)
+}
+
+/// stops whatever daemon this instance already has before starting another
+fn replace_existing() -> io::Result<()> {
+    match close_existing()? {
+        Stopped::NotRunning => (),
+        stopped => info!("replacing running daemon: {}", stopped),
     }
+    Ok(())
+}
+
+/// appends, so output from earlier runs survives a restart
+fn private_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)
 }
 
 fn daemonize() -> Result<(), Box<dyn Error>> {
-    close_existing();
+    replace_existing()?;
     Daemonize::new()
         .pid_file(PID_PATH.as_path())
         .chown_pid_file(true)
-        .working_directory(CALTRAIND_PATH.as_path())
-        .stdout(File::create(STDOUT_PATH.as_path())?)
-        .stderr(File::create(STDERR_PATH.as_path())?)
+        .working_directory(RUNTIME_PATH.as_path())
+        .umask(0o077)
+        .stdout(private_file(STDOUT_PATH.as_path())?)
+        .stderr(private_file(STDERR_PATH.as_path())?)
         .start()?;
     Ok(())
 }
 
+/// stays attached to the terminal or service manager, which collects stderr as the log, the
+/// returned pid file has to stay open for as long as the daemon runs
+fn foreground() -> Result<File, Box<dyn Error>> {
+    replace_existing()?;
+    Ok(pid_file::create(&PID_PATH)?)
+}
+
+/// runs the daemon, forking into the background unless `in_foreground` is set, as it should be
+/// under systemd, and serving `/metrics` on `metrics_addr` as well as the control socket
 pub fn start(
     n_threads: usize,
-    train_types: BTreeSet<TrainType>,
-    station: Station,
-    direction: Direction,
-    refresh_rate: Duration,
-    notify_at: Vec<u16>,
-    notify_after: Option<NaiveTime>,
+    profile: Profile,
+    source: StatusSource,
+    in_foreground: bool,
+    metrics_addr: Option<SocketAddr>,
 ) -> Result<(), Box<dyn Error>> {
-    daemonize()?;
+    let _pid_file = if in_foreground {
+        Some(foreground()?)
+    } else {
+        daemonize()?;
+        None
+    };
+    // lets `caltraind list` show how each instance was started
+    write(
+        CMDLINE_PATH.as_path(),
+        env::args().collect::<Vec<_>>().join(" "),
+    )?;
 
     let sys = System::new("caltraind");
 
-    CStatusFetcher::new(station, refresh_rate).start();
-    for n in notify_at {
-        Notifier::new(train_types.clone(), n, direction, notify_after).start();
+    let station = profile.station;
+    let tracker = StatusTracker::new(&profile).start();
+
+    let return_profile = profile.return_profile();
+    let mut health_policy = HealthPolicy {
+        stations: vec![station],
+        fetch_within: None,
+        stale_after: None,
+        started: Instant::now(),
+    };
+    match source {
+        StatusSource::Live(fetch_policy) => {
+            // a poll may be skipped before anything is wrong, an outage itself shows up as a
+            // stale status rather than the fetcher backing off
+            health_policy.fetch_within =
+                Some(fetch_policy.longest_gap() + fetch_policy.max_refresh_rate);
+            health_policy.stale_after = Some(fetch_policy.stale_after);
+            if let Some(back) = &return_profile {
+                CStatusFetcher::new(back.station, fetch_policy.clone(), vec![back.clone()]).start();
+                health_policy.stations.push(back.station);
+            }
+            CStatusFetcher::new(station, fetch_policy, vec![profile.clone()]).start();
+        }
+        StatusSource::Replay { dir, speed } => {
+            if let Some(back) = &return_profile {
+                match Replayer::new(back.station, &dir, speed) {
+                    Ok(replayer) => {
+                        replayer.start();
+                        health_policy.stations.push(back.station);
+                    }
+                    Err(e) => warn!("not replaying the return trip: {}", e),
+                }
+            }
+            Replayer::new(station, &dir, speed)?.start();
+        }
+    }
+    if let Some(reminder) = ReturnReminder::new(&profile) {
+        reminder.start();
+    }
+    let notifier = Notifier::new(profile.clone()).start();
+    Shutdown::new(notifier.clone()).start();
+
+    let server = HttpServer::new(move || {
+        App::new()
+            .data(tracker.clone())
+            .data(notifier.clone())
+            .data(health_policy.clone())
+            .route("/status", web::get().to_async(api::status))
+            .route("/health", web::get().to(api::health))
+            .route("/metrics", web::get().to(api::metrics))
+            .route(
+                "/trains/{train}/{action}",
+                web::post().to(api::train_action),
+            )
+    })
+    .workers(n_threads)
+    .disable_signals();
+    // systemd keeps the socket it created for the next start
+    let activated = systemd::activated_listener();
+    let owns_socket = activated.is_none();
+    match activated {
+        Some(listener) => {
+            server.listen_uds(listener)?.start();
+        }
+        None => {
+            server.bind_uds(SOCKET_PATH.as_path())?.start();
+            // the runtime directory already keeps other users out, this covers a directory
+            // given with looser permissions than expected
+            set_permissions(SOCKET_PATH.as_path(), Permissions::from_mode(0o600))?;
+        }
+    }
+
+    // only metrics go over tcp, the control socket stays the one way to act on trains
+    if let Some(addr) = metrics_addr {
+        HttpServer::new(|| App::new().route("/metrics", web::get().to(api::metrics)))
+            .workers(1)
+            .disable_signals()
+            .bind(addr)?
+            .start();
+        info!("serving metrics on http://{}/metrics", addr);
     }
 
-    HttpServer::new(App::new)
-        .workers(n_threads)
-        .bind_uds(SOCKET_PATH.as_path())?
-        .start();
+    if let Some(interval) = systemd::watchdog_interval() {
+        Watchdog::new(interval).start();
+    }
+    info!(
+        "started instance {} for {} {:?}, pid {}",
+        instance(),
+        profile.station,
+        profile.direction,
+        process::id()
+    );
+    if let Err(e) = systemd::notify("READY=1") {
+        warn!("error notifying systemd: {}", e);
+    }
 
     sys.run()?;
 
+    if owns_socket {
+        if let Err(e) = remove_file(SOCKET_PATH.as_path()) {
+            warn!("error removing {}: {}", SOCKET_PATH.display(), e);
+        }
+    }
+    if let Err(e) = remove_file(PID_PATH.as_path()) {
+        warn!("error removing {}: {}", PID_PATH.display(), e);
+    }
+    info!("stopped");
+
     Ok(())
 }
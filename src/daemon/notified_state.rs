@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::cfg::{save_json, DATA_PATH};
+use crate::daemon::profile::Profile;
+
+/// trains run past midnight, so a service day is taken to start at 3am
+const SERVICE_DAY_START_HOUR: i64 = 3;
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+pub fn service_date(now: DateTime<Local>) -> NaiveDate {
+    (now - Duration::hours(SERVICE_DAY_START_HOUR))
+        .date()
+        .naive_local()
+}
+
+/// where a profile's notified trains are kept, one file per station and direction
+pub fn path(profile: &Profile) -> PathBuf {
+    DATA_PATH.join(format!(
+        "notified-{:?}-{:?}.json",
+        profile.station, profile.direction
+    ))
+}
+
+/// the offsets that have gone off for each train on a service day and what the rider chose to
+/// skip or snooze, saved so a restarted daemon doesn't alert again
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct NotifiedState {
+    service_date: String,
+    /// train and offset pairs
+    notified: BTreeSet<(u16, u16)>,
+    #[serde(default)]
+    skipped: BTreeSet<u16>,
+    /// when each snooze ends, in unix seconds
+    #[serde(default)]
+    snoozed: BTreeMap<u16, i64>,
+}
+
+impl NotifiedState {
+    /// `stages` counts how many of `notify_at`, earliest first, have gone off for each train
+    pub fn new(
+        date: NaiveDate,
+        stages: &BTreeMap<u16, usize>,
+        notify_at: &[u16],
+        skipped: &BTreeSet<u16>,
+        snoozed: &BTreeMap<u16, DateTime<Local>>,
+    ) -> Self {
+        NotifiedState {
+            service_date: date.format(DATE_FORMAT).to_string(),
+            skipped: skipped.clone(),
+            snoozed: snoozed
+                .iter()
+                .map(|(train, until)| (*train, until.timestamp()))
+                .collect(),
+            notified: stages
+                .iter()
+                .flat_map(|(train, stage)| {
+                    notify_at
+                        .iter()
+                        .take(*stage)
+                        .map(move |offset| (*train, *offset))
+                })
+                .collect(),
+        }
+    }
+
+    /// how many of `notify_at` have gone off for each train, nothing if the state is from
+    /// another service day
+    pub fn stages(&self, date: NaiveDate, notify_at: &[u16]) -> BTreeMap<u16, usize> {
+        let mut stages = BTreeMap::new();
+        if !self.is_for(date) {
+            return stages;
+        }
+        for (train, offset) in &self.notified {
+            if notify_at.contains(offset) {
+                *stages.entry(*train).or_insert(0) += 1;
+            }
+        }
+        stages
+    }
+
+    /// trains the rider skipped, nothing if the state is from another service day
+    pub fn skipped(&self, date: NaiveDate) -> BTreeSet<u16> {
+        if !self.is_for(date) {
+            return BTreeSet::new();
+        }
+        self.skipped.clone()
+    }
+
+    /// when each snoozed train comes back, nothing if the state is from another service day
+    pub fn snoozed(&self, date: NaiveDate) -> BTreeMap<u16, DateTime<Local>> {
+        if !self.is_for(date) {
+            return BTreeMap::new();
+        }
+        self.snoozed
+            .iter()
+            .map(|(train, until)| (*train, Local.timestamp(*until, 0)))
+            .collect()
+    }
+
+    fn is_for(&self, date: NaiveDate) -> bool {
+        self.service_date == date.format(DATE_FORMAT).to_string()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        save_json(path, self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stages_round_trip_within_a_service_day() {
+        let notify_at = [15, 10, 5];
+        let mut stages = BTreeMap::new();
+        stages.insert(101, 2);
+        stages.insert(305, 1);
+
+        // just after midnight still belongs to the previous day's service
+        let evening = service_date(Local.ymd(2019, 11, 4).and_hms(23, 50, 0));
+        let after_midnight = service_date(Local.ymd(2019, 11, 5).and_hms(0, 20, 0));
+        assert_eq!(evening, after_midnight);
+
+        let skipped = vec![411].into_iter().collect();
+        let mut snoozed = BTreeMap::new();
+        snoozed.insert(101, Local.ymd(2019, 11, 4).and_hms(23, 55, 0));
+
+        let state = NotifiedState::new(evening, &stages, &notify_at, &skipped, &snoozed);
+        let saved: NotifiedState =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(saved.stages(after_midnight, &notify_at), stages);
+        assert_eq!(saved.skipped(after_midnight), skipped);
+        assert_eq!(saved.snoozed(after_midnight), snoozed);
+
+        let next_day = service_date(Local.ymd(2019, 11, 5).and_hms(7, 0, 0));
+        assert!(saved.stages(next_day, &notify_at).is_empty());
+        assert!(saved.skipped(next_day).is_empty());
+        assert!(saved.snoozed(next_day).is_empty());
+    }
+}
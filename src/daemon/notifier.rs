@@ -1,108 +1,755 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration as StdDuration, Instant};
 
 use actix::prelude::*;
-use actix_broker::BrokerSubscribe;
-use chrono::{Local, NaiveTime};
-use notify_rust::{Notification, Timeout};
+use actix_broker::{BrokerIssue, BrokerSubscribe, SystemBroker};
+use chrono::{DateTime, Local};
+use log::{debug, error, info, trace, warn};
+use notify_rust::{Notification, NotificationHint, NotificationUrgency, Timeout};
 use time::Duration;
 
 use crate::caltrain_status::Direction::Northbound;
-use crate::caltrain_status::{CaltrainStatus, Direction, IncomingTrain, TrainType};
-use std::ops::Add;
+use crate::caltrain_status::{IncomingTrain, TrainType};
+use crate::daemon::cstatus_fetcher::{MarkupChanged, StationStatus, StatusStale};
+use crate::daemon::metrics;
+use crate::daemon::notified_state::{self, service_date, NotifiedState};
+use crate::daemon::profile::{Profile, Templates, Urgency};
+use crate::daemon::shutdown::Flush;
+use crate::parse::{parse_named, ParseError};
+use crate::schedule::minutes_to_arrival;
+use crate::station::Station;
 
+/// issued when a train that was notified about leaves the station, taken to mean the rider
+/// was on it
+#[derive(Clone, Debug)]
+pub struct TrainDeparted {
+    pub station: Station,
+    pub train: u16,
+    pub at: DateTime<Local>,
+}
+
+impl Message for TrainDeparted {
+    type Result = ();
+}
+
+/// what the rider chose to do about a train, from a notification or the control socket
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// notify about the train again in `SNOOZE_MINUTES`
+    Snooze,
+    /// no more notifications for the train
+    Skip,
+    /// the rider is taking the train, arming the return trip reminders
+    Take,
+}
+
+pub const SNOOZE_MINUTES: i64 = 5;
+
+/// most notifications left waiting for a choice at once, each holds a thread until it's closed
+const MAX_WAITING: usize = 16;
+
+/// threads currently waiting on a notification's actions
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+/// how long shutting down waits for notifications still being shown to finish, within
+/// `shutdown::FLUSH_TIMEOUT`
+const SETTLE_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
+/// freedesktop sound theme name played with critical notifications
+const CRITICAL_SOUND: &str = "alarm-clock-elapsed";
+
+impl Action {
+    const ALL: [Action; 3] = [Action::Snooze, Action::Skip, Action::Take];
+
+    /// identifier used for notification actions and the control socket
+    pub fn key(self) -> &'static str {
+        match self {
+            Action::Snooze => "snooze",
+            Action::Skip => "skip",
+            Action::Take => "take",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Action::Snooze => "Snooze 5 min",
+            Action::Skip => "Skip this train",
+            Action::Take => "Taking it",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.key())
+    }
+}
+
+impl FromStr for Action {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_named(
+            "action",
+            s,
+            Action::ALL
+                .iter()
+                .map(|action| (*action, action.to_string(), vec![action.to_string()])),
+        )
+    }
+}
+
+/// issued to every actor interested in what the rider chose
+#[derive(Clone, Debug)]
+pub struct TrainAction {
+    pub train: u16,
+    pub action: Action,
+}
+
+impl Message for TrainAction {
+    type Result = ();
+}
+
+/// sent back by the thread showing a notification, so later stages can replace it
+struct Shown {
+    train: u16,
+    id: u32,
+}
+
+impl Message for Shown {
+    type Result = ();
+}
+
+/// sent back once the rider dismisses a notification, so it isn't brought back by refreshes
+struct Closed {
+    train: u16,
+    id: u32,
+}
+
+impl Message for Closed {
+    type Result = ();
+}
+
+/// sent straight to a notifier, which passes it on to everyone as a `TrainAction`
+pub struct Choose(pub TrainAction);
+
+impl Message for Choose {
+    type Result = ();
+}
+
+/// how far a train's notification has escalated
+struct Notified {
+    /// number of offsets that have gone off
+    stage: usize,
+    /// the notification to replace at the next stage, once it's been shown
+    id: Option<u32>,
+}
+
+/// notifies about each train once per `notify_at` offset, escalating a single notification
 pub struct Notifier {
-    trains_notified: BTreeSet<u16>,
-    notify_at: u16,
-    notify_types: BTreeSet<TrainType>,
-    direction: Direction,
-    notify_after: Option<NaiveTime>,
+    trains_notified: BTreeMap<u16, Notified>,
+    skipped: BTreeSet<u16>,
+    snoozed: BTreeMap<u16, DateTime<Local>>,
+    /// departure time each train was first expected at, for working out delays
+    first_estimate: BTreeMap<u16, DateTime<Local>>,
+    /// offsets in minutes, earliest first
+    notify_at: Vec<u16>,
+    profile: Profile,
+    stale_warned: bool,
+    state_path: PathBuf,
+    /// what was last written to `state_path`
+    saved: NotifiedState,
 }
 
 impl Notifier {
-    pub fn new(
-        notify_types: BTreeSet<TrainType>,
-        notify_at: u16,
-        direction: Direction,
-        notify_after: Option<NaiveTime>,
-    ) -> Self {
+    pub fn new(profile: Profile) -> Self {
+        let mut notify_at = profile.notify_at.clone();
+        notify_at.sort_by(|a, b| b.cmp(a));
+        notify_at.dedup();
+
+        let state_path = notified_state::path(&profile);
+        let saved = NotifiedState::load(&state_path).unwrap_or_else(|e| {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("error reading {}: {}", state_path.display(), e);
+            }
+            NotifiedState::default()
+        });
+        let today = service_date(Local::now());
+        let trains_notified = saved
+            .stages(today, &notify_at)
+            .into_iter()
+            .map(|(train, stage)| (train, Notified { stage, id: None }))
+            .collect();
+
         Notifier {
             notify_at,
-            notify_types,
-            trains_notified: BTreeSet::new(),
-            direction,
-            notify_after,
+            profile,
+            trains_notified,
+            skipped: saved.skipped(today),
+            snoozed: saved.snoozed(today),
+            first_estimate: BTreeMap::new(),
+            stale_warned: false,
+            state_path,
+            saved,
+        }
+    }
+
+    /// saves which offsets have gone off and what was skipped or snoozed, if that changed since
+    /// the last save
+    fn persist(&mut self) {
+        let stages = self
+            .trains_notified
+            .iter()
+            .map(|(train, notified)| (*train, notified.stage))
+            .collect();
+        let state = NotifiedState::new(
+            service_date(Local::now()),
+            &stages,
+            &self.notify_at,
+            &self.skipped,
+            &self.snoozed,
+        );
+        if state == self.saved {
+            return;
+        }
+        if let Err(e) = state.save(&self.state_path) {
+            error!("error saving {}: {}", self.state_path.display(), e);
+        }
+        self.saved = state;
+    }
+
+    /// minutes the train is running behind the first departure estimate seen for it
+    fn delay(&self, train: &IncomingTrain) -> Option<i64> {
+        let first = self.first_estimate.get(&train.get_id())?;
+        let departs = Local::now() + Duration::minutes(i64::from(train.get_min_till_departure()));
+        Some((departs - *first).num_minutes())
+    }
+
+    /// how many offsets have gone off for a train with `min_till_departure`
+    fn stage(&self, min_till_departure: u16) -> usize {
+        let leave_in = min_till_departure.saturating_sub(self.profile.travel.lead_minutes());
+        self.notify_at.iter().filter(|n| leave_in <= **n).count()
+    }
+
+    /// why `train` shouldn't be notified about on this update, if there's a reason
+    fn held_back(&self, train: &IncomingTrain, now: DateTime<Local>) -> Option<String> {
+        let id = train.get_id();
+        let min_till_departure = train.get_min_till_departure();
+        if let Some(after) = self.profile.notify_after {
+            let departs =
+                now.naive_local().time() + Duration::minutes(i64::from(min_till_departure));
+            if departs < after {
+                return Some(format!("departs before {}", after.format("%H:%M")));
+            }
+        }
+        if !self.profile.train_types.contains(&train.get_train_type()) {
+            return Some(format!("{} trains aren't tracked", train.get_train_type()));
+        }
+        if !self.profile.travel.can_reach(min_till_departure) {
+            return Some(format!("can't be reached in {} min", min_till_departure));
+        }
+        let reached = self
+            .trains_notified
+            .get(&id)
+            .map_or(0, |notified| notified.stage);
+        let stage = self.stage(min_till_departure);
+        if stage <= reached {
+            return Some(format!(
+                "stage {} of {} already notified, departs in {} min",
+                reached,
+                self.notify_at.len(),
+                min_till_departure
+            ));
+        }
+        if self.skipped.contains(&id) {
+            return Some("skipped".to_string());
+        }
+        match self.snoozed.get(&id) {
+            Some(until) if *until > now => Some(format!(
+                "snoozed until {}",
+                until.format("%l:%M%p").to_string().trim()
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// low urgency for the first of several offsets, critical for the last and normal in between,
+/// nothing special when there's only one
+fn escalation(stage: usize, stages: usize) -> Option<Urgency> {
+    if stages < 2 {
+        None
+    } else if stage == 1 {
+        Some(Urgency::Low)
+    } else if stage >= stages {
+        Some(Urgency::Critical)
+    } else {
+        Some(Urgency::Normal)
+    }
+}
+
+/// the fields of `DEPARTURE_FIELDS` for `train` leaving `profile.station`
+pub fn train_fields(
+    profile: &Profile,
+    train: &IncomingTrain,
+    delay: Option<i64>,
+) -> BTreeMap<&'static str, String> {
+    let now = Local::now();
+    let clock = |minutes: u16| {
+        (now + Duration::minutes(i64::from(minutes)))
+            .format("%l:%M%p")
+            .to_string()
+            .trim()
+            .to_string()
+    };
+    let min_till_departure = train.get_min_till_departure();
+    let leave_in = min_till_departure.saturating_sub(profile.travel.lead_minutes());
+
+    let mut fields = BTreeMap::new();
+    fields.insert("train", train.get_id().to_string());
+    fields.insert("type", train.get_train_type().to_string());
+    fields.insert("station", profile.station.to_string());
+    fields.insert("direction", format!("{:?}", profile.direction));
+    fields.insert("minutes", min_till_departure.to_string());
+    fields.insert("time", clock(min_till_departure));
+    fields.insert("leave_in", leave_in.to_string());
+    fields.insert(
+        "leave",
+        if leave_in == 0 {
+            "leave now".to_string()
+        } else {
+            format!("leave in {} minutes", leave_in)
+        },
+    );
+    fields.insert("travel_minutes", profile.travel.minutes.to_string());
+    fields.insert("travel_mode", profile.travel.mode.to_string());
+    let late = delay.filter(|delay| *delay > 0);
+    fields.insert(
+        "status",
+        match (min_till_departure, late) {
+            (0, _) => "departing now".to_string(),
+            (_, Some(late)) => format!("running {} min late", late),
+            (_, None) => "on time".to_string(),
+        },
+    );
+    if let Some(delay) = late {
+        fields.insert("delay", delay.to_string());
+    }
+    if let Some(destination) = profile.destination {
+        fields.insert("destination", destination.to_string());
+        let arrival = minutes_to_arrival(train, profile.direction, profile.station, destination);
+        if let Some(minutes) = arrival {
+            fields.insert("eta", clock(minutes));
+        }
+    }
+    fields
+}
+
+/// writes the log sink's line, once per notification rather than per refresh
+pub fn log_sink(templates: &Templates, fields: &BTreeMap<&str, String>) {
+    if let Some(log) = &templates.log {
+        info!("{}", log.render(fields));
+        metrics::notified("log", true);
+    }
+}
+
+/// builds the desktop notification for `train_type`, `urgency` overrides the train type's style
+pub fn render(
+    templates: &Templates,
+    train_type: TrainType,
+    fields: &BTreeMap<&str, String>,
+    urgency: Option<Urgency>,
+) -> Notification {
+    let mut notification = Notification::new();
+    notification
+        .summary(&templates.summary.render(fields))
+        .body(&templates.body.render(fields))
+        .timeout(Timeout::Never);
+    let style = templates.styles.get(&train_type);
+    if let Some(urgency) = urgency.or_else(|| style.and_then(|style| style.urgency)) {
+        notification.urgency(match urgency {
+            Urgency::Low => NotificationUrgency::Low,
+            Urgency::Normal => NotificationUrgency::Normal,
+            Urgency::Critical => NotificationUrgency::Critical,
+        });
+        if urgency == Urgency::Critical {
+            notification.hint(NotificationHint::SoundName(CRITICAL_SOUND.to_string()));
         }
     }
+    if let Some(icon) = style.and_then(|style| style.icon.as_ref()) {
+        notification.icon(icon);
+    }
+    notification
 }
 
 impl Actor for Notifier {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        self.subscribe_system_async::<CaltrainStatus>(ctx);
+        self.subscribe_system_async::<StationStatus>(ctx);
+        self.subscribe_system_async::<StatusStale>(ctx);
+        self.subscribe_system_async::<MarkupChanged>(ctx);
+        self.subscribe_system_async::<TrainAction>(ctx);
+    }
+}
+
+impl Handler<Flush> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, _: Flush, _: &mut Self::Context) -> Self::Result {
+        self.persist();
+        // their actions would go nowhere once the daemon exits, and closing them lets the
+        // threads waiting on them finish
+        for id in self
+            .trains_notified
+            .values()
+            .filter_map(|notified| notified.id)
+        {
+            close_notification(id);
+        }
+        let started = Instant::now();
+        while WAITING.load(Ordering::SeqCst) > 0 && started.elapsed() < SETTLE_TIMEOUT {
+            thread::sleep(StdDuration::from_millis(50));
+        }
+        let waiting = WAITING.load(Ordering::SeqCst);
+        if waiting > 0 {
+            warn!("{} notifications still open at exit", waiting);
+        }
     }
 }
 
-impl Handler<CaltrainStatus> for Notifier {
+impl Handler<StationStatus> for Notifier {
     type Result = ();
 
-    fn handle(&mut self, status: CaltrainStatus, _: &mut Self::Context) -> Self::Result {
-        let (northbound, southbound) = status.get_status();
+    fn handle(&mut self, update: StationStatus, ctx: &mut Self::Context) -> Self::Result {
+        if update.station != self.profile.station {
+            return;
+        }
+        self.stale_warned = false;
+
+        let (northbound, southbound) = update.status.get_status();
 
-        let incoming_trains = if self.direction == Northbound {
+        let incoming_trains = if self.profile.direction == Northbound {
             northbound
         } else {
             southbound
         };
 
-        let mut tmp = BTreeSet::new();
-        std::mem::swap(&mut self.trains_notified, &mut tmp);
-        self.trains_notified = tmp
-            .into_iter()
-            .filter(|notified| {
-                incoming_trains
-                    .iter()
-                    .any(|incoming| incoming.get_id() == *notified)
+        let still_coming = |train: &u16| incoming_trains.iter().any(|t| t.get_id() == *train);
+        let departed: Vec<(u16, Option<u32>)> = self
+            .trains_notified
+            .iter()
+            .filter(|(train, _)| !still_coming(train))
+            .map(|(train, notified)| (*train, notified.id))
+            .collect();
+        self.trains_notified.retain(|train, _| still_coming(train));
+        self.skipped.retain(|train| still_coming(train));
+        self.snoozed.retain(|train, _| still_coming(train));
+        self.first_estimate.retain(|train, _| still_coming(train));
+        let now = Local::now();
+        for train in incoming_trains {
+            self.first_estimate
+                .entry(train.get_id())
+                .or_insert_with(|| {
+                    now + Duration::minutes(i64::from(train.get_min_till_departure()))
+                });
+        }
+        for (train, id) in departed {
+            // a departed train's actions are no use, and closing lets its thread finish
+            if let Some(id) = id {
+                close_notification(id);
+            }
+            if self.skipped.contains(&train) {
+                continue;
+            }
+            debug!("train {} departed {}", train, self.profile.station);
+            self.issue_async::<SystemBroker, _>(TrainDeparted {
+                station: self.profile.station,
+                train,
+                at: Local::now(),
+            });
+        }
+
+        let tracked = incoming_trains;
+        let trains_to_notify: Vec<_> = incoming_trains
+            .iter()
+            .filter(|train| match self.held_back(train, now) {
+                Some(reason) => {
+                    trace!("not notifying about train {}: {}", train.get_id(), reason);
+                    false
+                }
+                None => true,
             })
             .collect();
 
-        let mut incoming_trains: Box<dyn Iterator<Item = &IncomingTrain>> =
-            Box::new(incoming_trains.into_iter());
+        for train in trains_to_notify.iter() {
+            let stage = self.stage(train.get_min_till_departure());
+            debug!(
+                "notifying about train {} departing {} in {} min, stage {} of {}",
+                train.get_id(),
+                self.profile.station,
+                train.get_min_till_departure(),
+                stage,
+                self.notify_at.len()
+            );
+            let fields = train_fields(&self.profile, train, self.delay(train));
+            let urgency = escalation(stage, self.notify_at.len());
+            log_sink(&self.profile.templates, &fields);
+            let notification = render(
+                &self.profile.templates,
+                train.get_train_type(),
+                &fields,
+                urgency,
+            );
+            let notified = self
+                .trains_notified
+                .entry(train.get_id())
+                .or_insert(Notified { stage, id: None });
+            notified.stage = stage;
+            let replacing = notified.id;
+            self.snoozed.remove(&train.get_id());
+            show_train_notification(train.get_id(), notification, replacing, ctx.address());
+        }
 
-        if let Some(t) = self.notify_after {
-            let now = Local::now().naive_local().time();
-            incoming_trains = Box::new(incoming_trains.filter(move |train| {
-                let time_till_departure = Duration::minutes(train.get_min_till_departure() as i64);
-                now.add(time_till_departure) >= t
-            }));
+        if self.profile.countdown {
+            let escalated: BTreeSet<u16> = trains_to_notify.iter().map(|t| t.get_id()).collect();
+            for train in tracked {
+                if escalated.contains(&train.get_id()) || self.skipped.contains(&train.get_id()) {
+                    continue;
+                }
+                let (stage, id) = match self.trains_notified.get(&train.get_id()) {
+                    Some(Notified {
+                        stage,
+                        id: Some(id),
+                    }) => (*stage, *id),
+                    _ => continue,
+                };
+                let fields = train_fields(&self.profile, train, self.delay(train));
+                let urgency = escalation(stage, self.notify_at.len());
+                let mut notification = render(
+                    &self.profile.templates,
+                    train.get_train_type(),
+                    &fields,
+                    urgency,
+                );
+                // only the escalation that brought it to this stage gets to play a sound
+                notification.hint(NotificationHint::SuppressSound(true));
+                add_actions(&mut notification);
+                notification.id(id);
+                refresh(&notification);
+            }
         }
 
-        let trains_to_notify: Vec<_> = incoming_trains
-            .filter(|incoming_train| self.notify_types.contains(&incoming_train.get_train_type()))
-            .filter(|incoming_train| incoming_train.get_min_till_departure() <= self.notify_at)
-            .filter(|incoming_train| !self.trains_notified.contains(&incoming_train.get_id()))
-            .collect();
+        self.persist();
+    }
+}
 
-        for train in trains_to_notify.into_iter() {
-            self.trains_notified.insert(train.get_id());
-            let notification_result = Notification::new()
-                .summary("Caltrain")
-                .body(
-                    format!(
-                        "{} train {} is departing in {} minutes at {}!",
-                        train.get_train_type(),
-                        train.get_id(),
-                        train.get_min_till_departure(),
-                        (Local::now() + Duration::minutes(train.get_min_till_departure() as i64))
-                            .format("%l:%M%p")
-                    )
-                    .as_str(),
-                )
-                .timeout(Timeout::Never)
-                .show();
-            if let Err(e) = notification_result {
-                eprintln!("error creating notification: {}", e);
+impl Handler<Shown> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, shown: Shown, _: &mut Self::Context) -> Self::Result {
+        if let Some(notified) = self.trains_notified.get_mut(&shown.train) {
+            notified.id = Some(shown.id);
+        }
+    }
+}
+
+impl Handler<Closed> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, closed: Closed, _: &mut Self::Context) -> Self::Result {
+        if let Some(notified) = self.trains_notified.get_mut(&closed.train) {
+            if notified.id == Some(closed.id) {
+                notified.id = None;
             }
         }
     }
 }
+
+impl Handler<Choose> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, Choose(chosen): Choose, _: &mut Self::Context) -> Self::Result {
+        self.issue_async::<SystemBroker, _>(chosen);
+    }
+}
+
+impl Handler<TrainAction> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, chosen: TrainAction, _: &mut Self::Context) -> Self::Result {
+        info!("{} train {}", chosen.action, chosen.train);
+        match chosen.action {
+            Action::Snooze => {
+                // step back a stage so the current one goes off again once the snooze is up
+                if let Some(notified) = self.trains_notified.get_mut(&chosen.train) {
+                    notified.stage = notified.stage.saturating_sub(1);
+                    self.snoozed.insert(
+                        chosen.train,
+                        Local::now() + Duration::minutes(SNOOZE_MINUTES),
+                    );
+                }
+                self.persist();
+            }
+            Action::Skip => {
+                self.skipped.insert(chosen.train);
+                let id = self
+                    .trains_notified
+                    .get(&chosen.train)
+                    .and_then(|notified| notified.id);
+                if let (true, Some(id)) = (self.profile.countdown, id) {
+                    close_notification(id);
+                }
+                self.persist();
+            }
+            Action::Take => (),
+        }
+    }
+}
+
+impl Handler<StatusStale> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, stale: StatusStale, _: &mut Self::Context) -> Self::Result {
+        if self.stale_warned {
+            return;
+        }
+        self.stale_warned = true;
+        warn!(
+            "status hasn't updated in {}s",
+            stale.since_last_update.as_secs()
+        );
+        show_notification(&format!(
+            "caltrain status has not updated in {} minutes, departure alerts may be missed",
+            stale.since_last_update.as_secs() / 60
+        ));
+    }
+}
+
+impl Handler<MarkupChanged> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, changed: MarkupChanged, _: &mut Self::Context) -> Self::Result {
+        show_notification(&format!(
+            "the caltrain page for {} has changed layout and can no longer be read, departure \
+             alerts are paused ({})",
+            changed.station, changed.error
+        ));
+    }
+}
+
+/// shows a departure notification with actions, waiting for the rider's choice on its own
+/// thread since that blocks until the notification is closed
+///
+/// a notification `replacing` an earlier one is updated in place, the thread waiting on the
+/// earlier one sees actions on it too; once `MAX_WAITING` notifications are open, new ones are
+/// shown without actions rather than holding yet another thread
+fn show_train_notification(
+    train: u16,
+    mut notification: Notification,
+    replacing: Option<u32>,
+    notifier: Addr<Notifier>,
+) {
+    if let Some(id) = replacing {
+        add_actions(&mut notification);
+        notification.id(id);
+        show(&notification);
+        return;
+    }
+    if WAITING.fetch_add(1, Ordering::SeqCst) >= MAX_WAITING {
+        WAITING.fetch_sub(1, Ordering::SeqCst);
+        warn!(
+            "{} notifications are waiting for a choice, showing train {} without actions",
+            MAX_WAITING, train
+        );
+        show(&notification);
+        return;
+    }
+    add_actions(&mut notification);
+    thread::spawn(move || {
+        match notification.show() {
+            Ok(handle) => {
+                metrics::notified("desktop", true);
+                let id = handle.id();
+                notifier.do_send(Shown { train, id });
+                handle.wait_for_action(|key| match key {
+                    "__closed" => notifier.do_send(Closed { train, id }),
+                    key => {
+                        if let Ok(action) = key.parse() {
+                            notifier.do_send(Choose(TrainAction { train, action }));
+                        }
+                    }
+                })
+            }
+            Err(e) => {
+                metrics::notified("desktop", false);
+                error!("error creating notification: {}", e)
+            }
+        }
+        WAITING.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+fn add_actions(notification: &mut Notification) {
+    for action in Action::ALL.iter() {
+        notification.action(action.key(), action.label());
+    }
+}
+
+pub fn show_notification(body: &str) {
+    show(
+        Notification::new()
+            .summary("Caltrain")
+            .body(body)
+            .timeout(Timeout::Never),
+    );
+}
+
+/// notifications can only be closed through their handle, which belongs to the thread waiting
+/// for actions, so expire it right away instead
+fn close_notification(id: u32) {
+    let closed = Notification::new()
+        .id(id)
+        .summary("Caltrain")
+        .hint(NotificationHint::Transient(true))
+        .timeout(Timeout::Milliseconds(1))
+        .show();
+    if let Err(e) = closed {
+        error!("error closing notification: {}", e);
+    }
+}
+
+pub fn show(notification: &Notification) {
+    let shown = notification.show();
+    metrics::notified("desktop", shown.is_ok());
+    if let Err(e) = shown {
+        error!("error creating notification: {}", e);
+    }
+}
+
+/// updates a notification already on screen, which doesn't count as notifying again
+fn refresh(notification: &Notification) {
+    if let Err(e) = notification.show() {
+        error!("error updating notification: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escalation_by_stage() {
+        assert_eq!(escalation(1, 1), None);
+        assert_eq!(escalation(1, 2), Some(Urgency::Low));
+        assert_eq!(escalation(2, 2), Some(Urgency::Critical));
+        assert_eq!(escalation(2, 3), Some(Urgency::Normal));
+        assert_eq!(escalation(3, 3), Some(Urgency::Critical));
+    }
+}
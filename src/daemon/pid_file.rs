@@ -0,0 +1,216 @@
+use std::env;
+use std::fmt;
+use std::fs::{read, remove_file, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// what stopping an instance came down to
+#[derive(Debug, PartialEq)]
+pub enum Stopped {
+    NotRunning,
+    /// the pid file was left behind by a daemon that's gone
+    Stale,
+    Terminated(i32),
+    /// the daemon ignored SIGTERM
+    Killed(i32),
+}
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stopped::NotRunning => write!(f, "not running"),
+            Stopped::Stale => write!(f, "not running, removed stale pid file"),
+            Stopped::Terminated(pid) => write!(f, "stopped pid {}", pid),
+            Stopped::Killed(pid) => write!(f, "killed pid {} after it ignored SIGTERM", pid),
+        }
+    }
+}
+
+fn nix_error(e: nix::Error) -> io::Error {
+    match e {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+/// whether a running daemon holds the lock on `file`, every daemon keeps its pid file locked
+/// until it exits
+fn locked(file: &File) -> io::Result<bool> {
+    match flock(file.as_raw_fd(), FlockArg::LockSharedNonblock) {
+        Ok(()) => {
+            flock(file.as_raw_fd(), FlockArg::Unlock).map_err(nix_error)?;
+            Ok(false)
+        }
+        Err(nix::Error::Sys(Errno::EAGAIN)) => Ok(true),
+        Err(e) => Err(nix_error(e)),
+    }
+}
+
+fn read_pid(mut file: &File, path: &Path) -> io::Result<i32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    contents.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} doesn't hold a pid: {:?}", path.display(), contents),
+        )
+    })
+}
+
+/// checks the process's command line where `/proc` is available, the lock already rules out
+/// most reused pids
+fn is_caltraind(pid: i32) -> bool {
+    let cmdline = match read(format!("/proc/{}/cmdline", pid)) {
+        Ok(cmdline) => cmdline,
+        Err(_) => return true,
+    };
+    let program = String::from_utf8_lossy(cmdline.split(|b| *b == 0).next().unwrap_or(&[]))
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let ours = env::current_exe().ok().and_then(|exe| {
+        exe.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    });
+    program.starts_with("caltraind") || Some(program) == ours
+}
+
+/// pid of the daemon holding the pid file at `path`, if one is running
+pub fn running(path: &Path) -> io::Result<Option<i32>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !locked(&file)? {
+        return Ok(None);
+    }
+    read_pid(&file, path).map(Some)
+}
+
+/// waits for the daemon to exit and release its lock, false if it's still running after
+/// `timeout`
+fn wait_for_exit(file: &File, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    while locked(file)? {
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(true)
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// stops the daemon holding the pid file at `path` with SIGTERM, giving it `timeout` to exit
+/// before using SIGKILL if `escalate` is set, refuses to signal anything that isn't caltraind
+pub fn stop(path: &Path, timeout: Duration, escalate: bool) -> io::Result<Stopped> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Stopped::NotRunning),
+        Err(e) => return Err(e),
+    };
+    if !locked(&file)? {
+        remove(path)?;
+        return Ok(Stopped::Stale);
+    }
+    let pid = read_pid(&file, path)?;
+    if !is_caltraind(pid) {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "pid {} from {} isn't caltraind, leaving it alone",
+                pid,
+                path.display()
+            ),
+        ));
+    }
+
+    let signals: &[Signal] = if escalate {
+        &[Signal::SIGTERM, Signal::SIGKILL]
+    } else {
+        &[Signal::SIGTERM]
+    };
+    let mut stopped = Stopped::Terminated(pid);
+    for signal in signals {
+        match signal::kill(Pid::from_raw(pid), *signal) {
+            Ok(()) | Err(nix::Error::Sys(Errno::ESRCH)) => (),
+            Err(e) => return Err(nix_error(e)),
+        }
+        if wait_for_exit(&file, timeout)? {
+            remove(path)?;
+            return Ok(stopped);
+        }
+        stopped = Stopped::Killed(pid);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!(
+            "pid {} is still running {}s after {:?}",
+            pid,
+            timeout.as_secs(),
+            signals[signals.len() - 1]
+        ),
+    ))
+}
+
+/// writes this process's pid to `path` and locks it, the lock lasts as long as the returned file
+/// stays open
+pub fn create(path: &Path) -> io::Result<File> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?;
+    flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).map_err(|e| match e {
+        nix::Error::Sys(Errno::EAGAIN) => io::Error::new(
+            io::ErrorKind::Other,
+            format!("{} is locked by another daemon", path.display()),
+        ),
+        e => nix_error(e),
+    })?;
+    file.set_len(0)?;
+    write!(file, "{}", process::id())?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lock_tells_running_from_stale() {
+        let path = env::temp_dir().join(format!("caltraind-pid-test-{}", process::id()));
+        let _ = remove_file(&path);
+        let timeout = Duration::from_secs(1);
+        assert_eq!(stop(&path, timeout, true).unwrap(), Stopped::NotRunning);
+
+        let lock = create(&path).unwrap();
+        assert_eq!(running(&path).unwrap(), Some(process::id() as i32));
+        assert!(create(&path).is_err());
+        drop(lock);
+
+        assert_eq!(running(&path).unwrap(), None);
+        assert_eq!(stop(&path, timeout, true).unwrap(), Stopped::Stale);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::NaiveTime;
+
+use crate::caltrain_status::{Direction, TrainType};
+use crate::cfg::TemplateConfig;
+use crate::parse::{parse_named, ParseError};
+use crate::schedule::direction_between;
+use crate::station::Station;
+use crate::template::{Template, TemplateError};
+
+/// fields available to departure templates
+pub const DEPARTURE_FIELDS: &[&str] = &[
+    "train",
+    "type",
+    "station",
+    "direction",
+    "minutes",
+    "time",
+    "leave",
+    "leave_in",
+    "travel_minutes",
+    "travel_mode",
+    "delay",
+    "status",
+    "destination",
+    "eta",
+];
+
+/// fields available to return trip templates, `morning_train` is the train taken that morning
+pub const RETURN_FIELDS: &[&str] = &[
+    "train",
+    "type",
+    "station",
+    "direction",
+    "minutes",
+    "time",
+    "leave",
+    "leave_in",
+    "travel_minutes",
+    "travel_mode",
+    "delay",
+    "status",
+    "destination",
+    "eta",
+    "morning_train",
+];
+
+const SUMMARY: &str = "Caltrain";
+const DEPARTURE_BODY: &str =
+    "{type} train {train} is departing {station} in {minutes} minutes at {time}![ Arriving {destination} around {eta}.]";
+const LEAVE_BODY: &str = "{leave} for {train} {type} at {station} (departs {time}, {travel_minutes} min {travel_mode})[ Arriving {destination} around {eta}.]";
+const RETURN_BODY: &str = "{type} {train} back to {destination} leaves {station} in {minutes} minutes[, arriving around {eta}] (you took {morning_train} this morning)";
+
+/// which trains to notify about and when
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub station: Station,
+    pub direction: Direction,
+    pub train_types: BTreeSet<TrainType>,
+    /// minutes before it's time to leave for the station
+    pub notify_at: Vec<u16>,
+    pub notify_after: Option<NaiveTime>,
+    pub travel: Travel,
+    /// where the rider is headed, for arrival estimates
+    pub destination: Option<Station>,
+    pub return_trip: Option<ReturnTrip>,
+    pub templates: Templates,
+    /// keep each train's notification up to date until it departs
+    pub countdown: bool,
+}
+
+impl Profile {
+    /// the trip back from the destination, if return reminders are set up
+    pub fn return_profile(&self) -> Option<Profile> {
+        let return_trip = self.return_trip.as_ref()?;
+        let destination = self.destination?;
+        Some(Profile {
+            station: destination,
+            direction: direction_between(destination, self.station)?,
+            train_types: self.train_types.clone(),
+            notify_at: self.notify_at.clone(),
+            notify_after: Some(return_trip.after),
+            // travel describes getting to the home station
+            travel: Travel {
+                minutes: 0,
+                mode: self.travel.mode,
+                buffer: self.travel.buffer,
+            },
+            destination: Some(self.station),
+            return_trip: None,
+            templates: return_trip.templates.clone(),
+            countdown: false,
+        })
+    }
+}
+
+/// evening reminders for getting back, only given on days a morning train was taken
+#[derive(Clone, Debug)]
+pub struct ReturnTrip {
+    /// trains taken before this are the morning commute, trains home are suggested after it
+    pub after: NaiveTime,
+    /// the last reasonable train home
+    pub last_train: Option<NaiveTime>,
+    /// minutes before `last_train` to warn
+    pub last_train_warning: u16,
+    pub templates: Templates,
+}
+
+/// how notifications are worded for each sink and styled for each train type
+#[derive(Clone, Debug)]
+pub struct Templates {
+    pub summary: Template,
+    /// desktop notification body
+    pub body: Template,
+    /// line written to the daemon log for every notification, if set
+    pub log: Option<Template>,
+    pub styles: BTreeMap<TrainType, Style>,
+}
+
+impl Templates {
+    /// departure notifications, worded around when to leave if it takes time to get to the
+    /// station
+    pub fn departure(
+        config: &TemplateConfig,
+        travel: Travel,
+        styles: BTreeMap<TrainType, Style>,
+    ) -> Result<Templates, TemplateError> {
+        let body = if travel.lead_minutes() == 0 {
+            DEPARTURE_BODY
+        } else {
+            LEAVE_BODY
+        };
+        Templates::new(config, body, DEPARTURE_FIELDS, styles)
+    }
+
+    pub fn return_trip(
+        config: &TemplateConfig,
+        styles: BTreeMap<TrainType, Style>,
+    ) -> Result<Templates, TemplateError> {
+        Templates::new(config, RETURN_BODY, RETURN_FIELDS, styles)
+    }
+
+    fn new(
+        config: &TemplateConfig,
+        body: &str,
+        fields: &[&'static str],
+        styles: BTreeMap<TrainType, Style>,
+    ) -> Result<Templates, TemplateError> {
+        let parse = |configured: &Option<String>, default: &str| {
+            Template::parse(configured.as_ref().map_or(default, String::as_str), fields)
+        };
+        Ok(Templates {
+            summary: parse(&config.summary, SUMMARY)?,
+            body: parse(&config.body, body)?,
+            log: match &config.log {
+                Some(log) => Some(Template::parse(log, fields)?),
+                None => None,
+            },
+            styles,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Style {
+    pub urgency: Option<Urgency>,
+    pub icon: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl FromStr for Urgency {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use Urgency::*;
+        parse_named(
+            "urgency",
+            s,
+            vec![
+                (Low, "low".to_string(), vec!["low".to_string()]),
+                (Normal, "normal".to_string(), vec!["normal".to_string()]),
+                (
+                    Critical,
+                    "critical".to_string(),
+                    vec!["critical".to_string()],
+                ),
+            ],
+        )
+    }
+}
+
+/// how long it takes to get to the station
+#[derive(Clone, Copy, Debug)]
+pub struct Travel {
+    pub minutes: u16,
+    pub mode: TravelMode,
+    /// extra minutes to arrive early by
+    pub buffer: u16,
+}
+
+impl Travel {
+    /// minutes before departure that it's time to leave
+    pub fn lead_minutes(&self) -> u16 {
+        self.minutes.saturating_add(self.buffer)
+    }
+
+    /// whether leaving now still gets to the station with the buffer to spare
+    pub fn can_reach(&self, min_till_departure: u16) -> bool {
+        min_till_departure >= self.lead_minutes()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TravelMode {
+    Walk,
+    Bike,
+    Drive,
+}
+
+impl fmt::Display for TravelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use TravelMode::*;
+        match self {
+            Walk => write!(f, "walk"),
+            Bike => write!(f, "bike ride"),
+            Drive => write!(f, "drive"),
+        }
+    }
+}
+
+impl FromStr for TravelMode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use TravelMode::*;
+        parse_named(
+            "travel mode",
+            s,
+            vec![
+                (Walk, "walk".to_string(), vec!["walk".to_string()]),
+                (Bike, "bike".to_string(), vec!["bike".to_string()]),
+                (Drive, "drive".to_string(), vec!["drive".to_string()]),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reaching_a_train_keeps_the_buffer() {
+        let travel = Travel {
+            minutes: 12,
+            mode: TravelMode::Walk,
+            buffer: 3,
+        };
+        assert!(travel.can_reach(15));
+        assert!(!travel.can_reach(14));
+
+        let huge = Travel {
+            minutes: u16::max_value(),
+            mode: TravelMode::Walk,
+            buffer: 5,
+        };
+        assert_eq!(huge.lead_minutes(), u16::max_value());
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::fs::{create_dir_all, read_dir, read_to_string, write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_broker::{BrokerIssue, BrokerSubscribe, SystemBroker};
+use chrono::Local;
+use log::{debug, error, info, warn};
+
+use crate::caltrain_status::CaltrainStatus;
+use crate::daemon::cstatus_fetcher::{FetchFailed, StationStatus};
+use crate::daemon::metrics;
+use crate::daemon::shutdown::ShuttingDown;
+use crate::station::Station;
+
+/// longest wait between replayed pages, so a very slow `speed` can't overflow the timer
+const MAX_GAP: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// a page saved by `record`, named `<unix millis>-<station>.html`
+struct Recording {
+    at_millis: i64,
+    path: PathBuf,
+}
+
+pub fn record(dir: &Path, station: Station, page: &str) -> io::Result<()> {
+    create_dir_all(dir)?;
+    let name = format!("{}-{:?}.html", Local::now().timestamp_millis(), station);
+    write(dir.join(name), page)
+}
+
+fn load(dir: &Path, station: Station) -> io::Result<VecDeque<Recording>> {
+    let station = format!("{:?}", station);
+    let mut recordings = vec![];
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let stem = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) => stem.to_string(),
+            None => continue,
+        };
+        let mut parts = stem.splitn(2, '-');
+        let at_millis = parts.next().and_then(|millis| millis.parse().ok());
+        if let (Some(at_millis), Some(name)) = (at_millis, parts.next()) {
+            if name == station {
+                recordings.push(Recording { at_millis, path });
+            }
+        }
+    }
+    recordings.sort_by_key(|r| r.at_millis);
+    Ok(recordings.into())
+}
+
+/// stands in for `CStatusFetcher`, issuing recorded pages with their original spacing divided
+/// by `speed`
+pub struct Replayer {
+    station: Station,
+    recordings: VecDeque<Recording>,
+    speed: f64,
+}
+
+impl Replayer {
+    pub fn new(station: Station, dir: &Path, speed: f64) -> io::Result<Self> {
+        let recordings = load(dir, station)?;
+        if recordings.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no recordings for {:?} in {}", station, dir.display()),
+            ));
+        }
+        Ok(Replayer {
+            station,
+            recordings,
+            speed,
+        })
+    }
+
+    fn play_next(&mut self, ctx: &mut <Replayer as Actor>::Context) {
+        let recording = match self.recordings.pop_front() {
+            Some(recording) => recording,
+            None => {
+                info!("replay of {} finished", self.station);
+                return;
+            }
+        };
+        match read_to_string(&recording.path) {
+            Ok(page) => match CaltrainStatus::from_html(page) {
+                Ok(status) => {
+                    debug!("replaying {}", recording.path.display());
+                    metrics::parsed(self.station, "ok");
+                    metrics::status_received(self.station, &status);
+                    self.issue_async::<SystemBroker, _>(StationStatus {
+                        station: self.station,
+                        status,
+                    })
+                }
+                Err(error) => {
+                    metrics::parsed(self.station, error.kind());
+                    warn!(
+                        "replayed page {} failed [{}]: {}",
+                        recording.path.display(),
+                        error.kind(),
+                        error
+                    );
+                    self.issue_async::<SystemBroker, _>(FetchFailed {
+                        station: self.station,
+                        error,
+                        at: Local::now(),
+                    });
+                }
+            },
+            Err(e) => error!("error reading {}: {}", recording.path.display(), e),
+        }
+        if let Some(next) = self.recordings.front() {
+            let gap = (next.at_millis - recording.at_millis).max(0) as f64 / self.speed;
+            ctx.run_later(
+                Duration::from_millis(gap.min(MAX_GAP.as_millis() as f64) as u64),
+                |replayer: &mut Self, ctx: &mut <Replayer as Actor>::Context| {
+                    replayer.play_next(ctx)
+                },
+            );
+        }
+    }
+}
+
+impl Actor for Replayer {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.subscribe_system_async::<ShuttingDown>(ctx);
+        self.play_next(ctx);
+    }
+}
+
+impl Handler<ShuttingDown> for Replayer {
+    type Result = ();
+
+    fn handle(&mut self, _: ShuttingDown, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::process;
+    use std::thread::sleep;
+
+    #[test]
+    fn record_then_load() {
+        let dir = env::temp_dir().join(format!("caltraind-replay-test-{}", process::id()));
+        record(&dir, Station::PaloAlto, "first").unwrap();
+        sleep(Duration::from_millis(5));
+        record(&dir, Station::SanFrancisco, "elsewhere").unwrap();
+        record(&dir, Station::PaloAlto, "second").unwrap();
+
+        let loaded = load(&dir, Station::PaloAlto).unwrap();
+        let pages: Vec<String> = loaded
+            .iter()
+            .map(|r| read_to_string(&r.path).unwrap())
+            .collect();
+        assert_eq!(pages, ["first", "second"]);
+        assert!(loaded[0].at_millis < loaded[1].at_millis);
+        assert!(load(&dir, Station::Gilroy).unwrap().is_empty());
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::BTreeSet;
+use std::fs::read_to_string;
+use std::io;
+use std::path::PathBuf;
+
+use actix::prelude::*;
+use actix_broker::BrokerSubscribe;
+use chrono::{DateTime, Duration, Local};
+use log::{error, warn};
+use notify_rust::Notification;
+use serde::{Deserialize, Serialize};
+
+use crate::caltrain_status::{CaltrainStatus, Direction, IncomingTrain};
+use crate::cfg::{save_json, DATA_PATH};
+use crate::daemon::cstatus_fetcher::StationStatus;
+use crate::daemon::notifier::{
+    log_sink, render, show, show_notification, train_fields, Action, TrainAction, TrainDeparted,
+};
+use crate::daemon::profile::{Profile, ReturnTrip};
+use crate::schedule::minutes_to_arrival;
+
+/// the train taken this morning, saved so a daemon restarted during the day still reminds about
+/// the way back
+#[derive(Serialize, Deserialize, Debug)]
+struct MorningTrain {
+    train: u16,
+    /// rfc 3339
+    at: String,
+    confirmed: bool,
+}
+
+fn morning_path(outbound: &Profile) -> PathBuf {
+    DATA_PATH.join(format!(
+        "morning-{:?}-{:?}.json",
+        outbound.station, outbound.direction
+    ))
+}
+
+fn load_morning(outbound: &Profile) -> io::Result<(TrainDeparted, bool)> {
+    let saved: MorningTrain = serde_json::from_str(&read_to_string(morning_path(outbound))?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let at = DateTime::parse_from_rfc3339(&saved.at)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let departed = TrainDeparted {
+        station: outbound.station,
+        train: saved.train,
+        at: at.with_timezone(&Local),
+    };
+    Ok((departed, saved.confirmed))
+}
+
+/// suggests trains home from the destination on days a morning train was taken, and warns
+/// ahead of the last reasonable one
+pub struct ReturnReminder {
+    outbound: Profile,
+    back: Profile,
+    trip: ReturnTrip,
+    morning_train: Option<TrainDeparted>,
+    /// the rider said which train they took, so departures of other trains don't count
+    morning_confirmed: bool,
+    suggested: BTreeSet<u16>,
+    last_status: Option<(CaltrainStatus, DateTime<Local>)>,
+}
+
+impl ReturnReminder {
+    pub fn new(outbound: &Profile) -> Option<Self> {
+        let back = outbound.return_profile()?;
+        let trip = outbound.return_trip.clone()?;
+        let (morning_train, morning_confirmed) = match load_morning(outbound) {
+            Ok((departed, confirmed)) => (Some(departed), confirmed),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("error reading {}: {}", morning_path(outbound).display(), e);
+                }
+                (None, false)
+            }
+        };
+        Some(ReturnReminder {
+            outbound: outbound.clone(),
+            back,
+            trip,
+            morning_train,
+            morning_confirmed,
+            suggested: BTreeSet::new(),
+            last_status: None,
+        })
+    }
+
+    fn save_morning(&self) {
+        let departed = match &self.morning_train {
+            Some(departed) => departed,
+            None => return,
+        };
+        let saved = MorningTrain {
+            train: departed.train,
+            at: departed.at.to_rfc3339(),
+            confirmed: self.morning_confirmed,
+        };
+        let path = morning_path(&self.outbound);
+        if let Err(e) = save_json(&path, &saved) {
+            error!("error saving {}: {}", path.display(), e);
+        }
+    }
+
+    fn morning_train_today(&self) -> Option<u16> {
+        self.morning_train
+            .as_ref()
+            .filter(|departed| departed.at.date() == Local::now().date())
+            .map(|departed| departed.train)
+    }
+
+    fn trains_home<'a>(&self, status: &'a CaltrainStatus) -> Vec<&'a IncomingTrain> {
+        let (northbound, southbound) = status.get_status();
+        let trains = match self.back.direction {
+            Direction::Northbound => northbound,
+            Direction::Southbound => southbound,
+        };
+        trains
+            .iter()
+            .filter(|train| self.back.train_types.contains(&train.get_train_type()))
+            .filter(|train| {
+                minutes_to_arrival(
+                    train,
+                    self.back.direction,
+                    self.back.station,
+                    self.outbound.station,
+                )
+                .is_some()
+            })
+            .collect()
+    }
+
+    /// logs a suggested train home and builds its notification
+    fn suggestion(&self, train: &IncomingTrain, morning_train: u16) -> Notification {
+        let mut fields = train_fields(&self.back, train, None);
+        fields.insert("morning_train", morning_train.to_string());
+        log_sink(&self.back.templates, &fields);
+        render(&self.back.templates, train.get_train_type(), &fields, None)
+    }
+
+    /// runs `last_train_warning` minutes before the last train every day
+    fn schedule_last_train(&mut self, ctx: &mut <ReturnReminder as Actor>::Context) {
+        let last_train = match self.trip.last_train {
+            Some(last_train) => last_train,
+            None => return,
+        };
+        let warn_at = last_train - Duration::minutes(i64::from(self.trip.last_train_warning));
+        let now = Local::now();
+        let mut next = match now.date().and_time(warn_at) {
+            Some(next) => next,
+            None => return,
+        };
+        if next <= now {
+            next = next + Duration::days(1);
+        }
+        let delay = (next - now).to_std().unwrap_or_default();
+        ctx.run_later(
+            delay,
+            |reminder: &mut Self, ctx: &mut <ReturnReminder as Actor>::Context| {
+                reminder.last_train_alert();
+                reminder.schedule_last_train(ctx);
+            },
+        );
+    }
+
+    fn last_train_alert(&self) {
+        let last_train = match (self.morning_train_today(), self.trip.last_train) {
+            (Some(_), Some(last_train)) => last_train,
+            _ => return,
+        };
+        // the latest train home seen on the live board that still leaves by the last train
+        let latest = self.last_status.as_ref().and_then(|(status, at)| {
+            self.trains_home(status)
+                .into_iter()
+                .map(|train| {
+                    let departs =
+                        *at + Duration::minutes(i64::from(train.get_min_till_departure()));
+                    (train, departs)
+                })
+                .filter(|(_, departs)| departs.time() <= last_train)
+                .max_by_key(|(_, departs)| *departs)
+        });
+        let body = match latest {
+            Some((train, departs)) => format!(
+                "last train home: {} {} leaves {} at {} for {}",
+                train.get_train_type(),
+                train.get_id(),
+                self.back.station,
+                departs.format("%l:%M%p").to_string().trim(),
+                self.outbound.station
+            ),
+            None => format!(
+                "last train home: the last train back to {} leaves {} around {}",
+                self.outbound.station,
+                self.back.station,
+                last_train.format("%l:%M%p").to_string().trim()
+            ),
+        };
+        show_notification(&body);
+    }
+}
+
+impl Actor for ReturnReminder {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.subscribe_system_async::<TrainDeparted>(ctx);
+        self.subscribe_system_async::<StationStatus>(ctx);
+        self.subscribe_system_async::<TrainAction>(ctx);
+        self.schedule_last_train(ctx);
+    }
+}
+
+impl Handler<TrainDeparted> for ReturnReminder {
+    type Result = ();
+
+    fn handle(&mut self, departed: TrainDeparted, _: &mut Self::Context) -> Self::Result {
+        if self.morning_confirmed && self.morning_train_today().is_some() {
+            return;
+        }
+        if departed.station == self.outbound.station && departed.at.time() < self.trip.after {
+            self.morning_train = Some(departed);
+            self.morning_confirmed = false;
+            self.save_morning();
+        }
+    }
+}
+
+impl Handler<TrainAction> for ReturnReminder {
+    type Result = ();
+
+    fn handle(&mut self, chosen: TrainAction, _: &mut Self::Context) -> Self::Result {
+        let now = Local::now();
+        if chosen.action == Action::Take && now.time() < self.trip.after {
+            self.morning_train = Some(TrainDeparted {
+                station: self.outbound.station,
+                train: chosen.train,
+                at: now,
+            });
+            self.morning_confirmed = true;
+            self.save_morning();
+        }
+    }
+}
+
+impl Handler<StationStatus> for ReturnReminder {
+    type Result = ();
+
+    fn handle(&mut self, update: StationStatus, _: &mut Self::Context) -> Self::Result {
+        if update.station != self.back.station {
+            return;
+        }
+        let now = Local::now();
+        let trains: Vec<IncomingTrain> = self
+            .trains_home(&update.status)
+            .into_iter()
+            .cloned()
+            .collect();
+        self.suggested
+            .retain(|id| trains.iter().any(|train| train.get_id() == *id));
+        self.last_status = Some((update.status, now));
+
+        let morning_train = match self.morning_train_today() {
+            Some(morning_train) if now.time() >= self.trip.after => morning_train,
+            _ => return,
+        };
+        let notify_at = self.back.notify_at.iter().max().cloned().unwrap_or(0);
+        for train in trains {
+            if train.get_min_till_departure() > notify_at
+                || self.suggested.contains(&train.get_id())
+            {
+                continue;
+            }
+            self.suggested.insert(train.get_id());
+            show(&self.suggestion(&train, morning_train));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::thread;
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_broker::{BrokerIssue, SystemBroker};
+use log::{error, info, warn};
+use signal_hook::iterator::Signals;
+use signal_hook::{SIGINT, SIGTERM};
+
+use crate::daemon::notifier::Notifier;
+use crate::daemon::systemd;
+
+/// how long the notifier gets to finish up before the daemon exits anyway, `kill` waits longer
+/// than this before escalating
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// issued once the daemon has been asked to exit, anything polling caltrain stops
+#[derive(Clone, Debug)]
+pub struct ShuttingDown;
+
+impl Message for ShuttingDown {
+    type Result = ();
+}
+
+/// answered once the notifier has handled everything sent before it, closed its notifications
+/// and saved its state
+pub struct Flush;
+
+impl Message for Flush {
+    type Result = ();
+}
+
+/// a signal caught by the listening thread
+struct Received(i32);
+
+impl Message for Received {
+    type Result = ();
+}
+
+/// stops the daemon cleanly on SIGTERM or SIGINT, the socket and pid file are removed by
+/// `daemon::start` once the system has stopped
+pub struct Shutdown {
+    notifier: Addr<Notifier>,
+    stopping: bool,
+}
+
+impl Shutdown {
+    pub fn new(notifier: Addr<Notifier>) -> Self {
+        Shutdown {
+            notifier,
+            stopping: false,
+        }
+    }
+}
+
+impl Actor for Shutdown {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signals = match Signals::new(&[SIGTERM, SIGINT]) {
+            Ok(signals) => signals,
+            Err(e) => {
+                error!("error listening for signals: {}", e);
+                return;
+            }
+        };
+        let addr = ctx.address();
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                addr.do_send(Received(signal));
+            }
+        });
+    }
+}
+
+impl Handler<Received> for Shutdown {
+    type Result = ();
+
+    fn handle(&mut self, Received(signal): Received, ctx: &mut Self::Context) -> Self::Result {
+        if self.stopping {
+            return;
+        }
+        self.stopping = true;
+        let name = match signal {
+            SIGTERM => "SIGTERM",
+            SIGINT => "SIGINT",
+            _ => "a signal",
+        };
+        info!("shutting down: received {}", name);
+        if let Err(e) = systemd::notify("STOPPING=1") {
+            warn!("error notifying systemd: {}", e);
+        }
+
+        self.issue_async::<SystemBroker, _>(ShuttingDown);
+        self.notifier
+            .send(Flush)
+            .into_actor(self)
+            .then(|_, _, _| {
+                System::current().stop();
+                actix::fut::ok(())
+            })
+            .spawn(ctx);
+        ctx.run_later(FLUSH_TIMEOUT, |_, _| {
+            warn!("notifier didn't finish in time, exiting anyway");
+            System::current().stop();
+        });
+    }
+}
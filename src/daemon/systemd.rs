@@ -0,0 +1,224 @@
+use std::env;
+use std::fs::{create_dir_all, write};
+use std::io;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use actix::prelude::*;
+use log::warn;
+
+use crate::cfg::{instance, CONFIG_DIR, DEFAULT_INSTANCE, SOCKET_PATH};
+
+/// first file descriptor systemd passes activated sockets on
+const LISTEN_FDS_START: i32 = 3;
+
+/// tells systemd about a state change, `Ok(false)` when not started by systemd with
+/// `Type=notify`
+pub fn notify(state: &str) -> io::Result<bool> {
+    let socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => socket,
+        None => return Ok(false),
+    };
+    if socket.to_string_lossy().starts_with('@') {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "abstract notify sockets aren't supported",
+        ));
+    }
+    UnixDatagram::unbound()?.send_to(state.as_bytes(), Path::new(&socket))?;
+    Ok(true)
+}
+
+/// how often to ping the watchdog, half of `WatchdogSec=` when it's set for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// the control socket when systemd created it through socket activation
+pub fn activated_listener() -> Option<UnixListener> {
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: i32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != process::id() || fds < 1 {
+        return None;
+    }
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    // systemd hands over ownership of the descriptor, nothing else in the process uses it
+    Some(unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+/// pings the systemd watchdog from the event loop, so a stalled loop gets the daemon restarted
+pub struct Watchdog {
+    interval: Duration,
+}
+
+impl Watchdog {
+    pub fn new(interval: Duration) -> Self {
+        Watchdog { interval }
+    }
+}
+
+impl Actor for Watchdog {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |_, _| {
+            if let Err(e) = notify("WATCHDOG=1") {
+                warn!("error pinging systemd watchdog: {}", e);
+            }
+        });
+    }
+}
+
+/// `caltraind`, or `caltraind-<instance>` for anything but the default instance, the service and
+/// socket units share the name so systemd pairs them
+pub fn unit_name(instance: &str) -> String {
+    if instance == DEFAULT_INSTANCE {
+        "caltraind".to_string()
+    } else {
+        format!("caltraind-{}", instance)
+    }
+}
+
+/// quotes arguments with whitespace or quotes the way systemd splits `ExecStart=`, and keeps it
+/// from expanding specifiers and variables
+fn quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+pub fn service_unit(command: &[String], instance: &str) -> String {
+    let command: Vec<String> = command.iter().map(|arg| quote(arg)).collect();
+    format!(
+        "[Unit]\n\
+         Description=caltrain departure notifications ({instance})\n\
+         After=graphical-session.target\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         ExecStart={command}\n\
+         WatchdogSec=60\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        instance = instance,
+        command = command.join(" ")
+    )
+}
+
+pub fn socket_unit(socket: &Path, instance: &str) -> String {
+    format!(
+        "[Unit]\n\
+         Description=caltrain departure notifications control socket ({instance})\n\
+         \n\
+         [Socket]\n\
+         ListenStream={socket}\n\
+         SocketMode=0600\n\
+         DirectoryMode=0700\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n",
+        instance = instance,
+        socket = socket.display()
+    )
+}
+
+/// writes a user service running `start --foreground` with `global_args` and `start_args`, and
+/// with `socket_activation` a socket unit for the control socket, returns the process exit code
+pub fn install(
+    unit_dir: Option<PathBuf>,
+    global_args: &[String],
+    start_args: &[String],
+    socket_activation: bool,
+) -> i32 {
+    let unit_dir = unit_dir.unwrap_or_else(|| match CONFIG_DIR.parent() {
+        Some(config_home) => config_home.join("systemd/user"),
+        None => PathBuf::from("/etc/systemd/user"),
+    });
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("unable to find the caltraind executable: {}", e);
+            return 1;
+        }
+    };
+    let mut command = vec![exe.display().to_string()];
+    command.extend(global_args.iter().cloned());
+    command.push("--instance".to_string());
+    command.push(instance().to_string());
+    command.push("start".to_string());
+    command.push("--foreground".to_string());
+    command.extend(start_args.iter().cloned());
+
+    let name = unit_name(instance());
+    let mut units = vec![(
+        unit_dir.join(format!("{}.service", name)),
+        service_unit(&command, instance()),
+    )];
+    if socket_activation {
+        units.push((
+            unit_dir.join(format!("{}.socket", name)),
+            socket_unit(&SOCKET_PATH, instance()),
+        ));
+    }
+    if let Err(e) = create_dir_all(&unit_dir) {
+        eprintln!("error creating {}: {}", unit_dir.display(), e);
+        return 1;
+    }
+    for (path, unit) in &units {
+        if let Err(e) = write(path, unit) {
+            eprintln!("error writing {}: {}", path.display(), e);
+            return 1;
+        }
+        println!("wrote {}", path.display());
+    }
+
+    let enable = if socket_activation {
+        format!("{}.socket", name)
+    } else {
+        format!("{}.service", name)
+    };
+    println!("now run:");
+    println!("  systemctl --user daemon-reload");
+    println!("  systemctl --user enable --now {}", enable);
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn service_unit_runs_in_foreground() {
+        let command: Vec<String> = [
+            "/usr/bin/caltraind",
+            "start",
+            "--foreground",
+            "-s",
+            "Palo Alto",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let unit = service_unit(&command, "work");
+        assert!(unit.contains("ExecStart=/usr/bin/caltraind start --foreground -s \"Palo Alto\"\n"));
+        assert!(unit.contains("Type=notify\n"));
+        assert_eq!(unit_name("work"), "caltraind-work");
+        assert_eq!(unit_name(DEFAULT_INSTANCE), "caltraind");
+    }
+}
@@ -0,0 +1,149 @@
+use actix::prelude::*;
+use actix_broker::BrokerSubscribe;
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::caltrain_status::{CaltrainStatus, Direction};
+use crate::daemon::cstatus_fetcher::{FetchFailed, StationStatus};
+use crate::daemon::profile::Profile;
+use crate::schedule::minutes_to_arrival;
+use crate::station::Station;
+
+/// keeps the latest fetch results around so they can be served over the control socket
+pub struct StatusTracker {
+    station: Station,
+    direction: Direction,
+    destination: Option<Station>,
+    last_status: Option<CaltrainStatus>,
+    last_update: Option<DateTime<Local>>,
+    last_error: Option<FetchFailed>,
+}
+
+impl StatusTracker {
+    pub fn new(profile: &Profile) -> Self {
+        StatusTracker {
+            station: profile.station,
+            direction: profile.direction,
+            destination: profile.destination,
+            last_status: None,
+            last_update: None,
+            last_error: None,
+        }
+    }
+
+    fn arrivals(&self) -> Vec<Arrival> {
+        let (status, updated, destination) =
+            match (&self.last_status, self.last_update, self.destination) {
+                (Some(status), Some(updated), Some(destination)) => (status, updated, destination),
+                _ => return vec![],
+            };
+        let (northbound, southbound) = status.get_status();
+        let trains = match self.direction {
+            Direction::Northbound => northbound,
+            Direction::Southbound => southbound,
+        };
+        trains
+            .iter()
+            .filter_map(|train| {
+                let minutes = minutes_to_arrival(train, self.direction, self.station, destination)?;
+                Some(Arrival {
+                    train: train.get_id(),
+                    at: (updated + Duration::minutes(i64::from(minutes))).to_rfc3339(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// estimated arrival of a train at the profile's destination
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Arrival {
+    pub train: u16,
+    pub at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorReport {
+    pub kind: String,
+    pub message: String,
+    pub exit_code: i32,
+    pub at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub station: Station,
+    pub direction: Direction,
+    pub destination: Option<Station>,
+    pub arrivals: Vec<Arrival>,
+    pub last_update: Option<String>,
+    pub status: Option<CaltrainStatus>,
+    pub last_error: Option<ErrorReport>,
+    /// true when the most recent fetch failed
+    pub failing: bool,
+}
+
+pub struct GetSnapshot;
+
+impl Message for GetSnapshot {
+    type Result = Snapshot;
+}
+
+impl Actor for StatusTracker {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.subscribe_system_async::<StationStatus>(ctx);
+        self.subscribe_system_async::<FetchFailed>(ctx);
+    }
+}
+
+impl Handler<StationStatus> for StatusTracker {
+    type Result = ();
+
+    fn handle(&mut self, update: StationStatus, _: &mut Self::Context) -> Self::Result {
+        if update.station != self.station {
+            return;
+        }
+        self.last_status = Some(update.status);
+        self.last_update = Some(Local::now());
+    }
+}
+
+impl Handler<FetchFailed> for StatusTracker {
+    type Result = ();
+
+    fn handle(&mut self, failed: FetchFailed, _: &mut Self::Context) -> Self::Result {
+        if failed.station != self.station {
+            return;
+        }
+        self.last_error = Some(failed);
+    }
+}
+
+impl Handler<GetSnapshot> for StatusTracker {
+    type Result = MessageResult<GetSnapshot>;
+
+    fn handle(&mut self, _: GetSnapshot, _: &mut Self::Context) -> Self::Result {
+        let failing = match (&self.last_error, &self.last_update) {
+            (Some(failed), Some(update)) => failed.at > *update,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        MessageResult(Snapshot {
+            station: self.station,
+            direction: self.direction,
+            destination: self.destination,
+            arrivals: self.arrivals(),
+            last_update: self.last_update.map(|t| t.to_rfc3339()),
+            status: self.last_status.clone(),
+            last_error: self.last_error.as_ref().map(|failed| ErrorReport {
+                kind: failed.error.kind().to_string(),
+                message: failed.error.to_string(),
+                exit_code: failed.error.exit_code(),
+                at: failed.at.to_rfc3339(),
+            }),
+            failing,
+        })
+    }
+}
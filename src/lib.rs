@@ -0,0 +1,9 @@
+//! caltrain status parsing, station data, typical run times, trip planning and notification
+//! templates, shared by the caltraind daemon and command line
+
+pub mod caltrain_status;
+pub mod parse;
+pub mod schedule;
+pub mod station;
+pub mod template;
+pub mod trip;
@@ -0,0 +1,74 @@
+use std::env;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::io;
+use std::path::PathBuf;
+
+use crate::station::{nearest, Station};
+
+/// something that can tell roughly where this machine is
+pub trait LocationProvider {
+    fn name(&self) -> String;
+
+    /// latitude and longitude, or `None` if the provider has no fix
+    fn locate(&self) -> Result<Option<(f64, f64)>, Box<dyn Error>>;
+}
+
+/// a file containing "latitude,longitude", kept up to date by whatever the user likes
+pub struct LocationFile(pub PathBuf);
+
+impl LocationProvider for LocationFile {
+    fn name(&self) -> String {
+        format!("location file {}", self.0.display())
+    }
+
+    fn locate(&self) -> Result<Option<(f64, f64)>, Box<dyn Error>> {
+        match read_to_string(&self.0) {
+            Ok(text) => Ok(Some(parse_coordinates(&text)?)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// stand-in for a geoclue client, reads "latitude,longitude" from `CALTRAIND_LOCATION` so a
+/// desktop agent can export the current position without caltraind talking to dbus
+pub struct GeoclueStub;
+
+impl LocationProvider for GeoclueStub {
+    fn name(&self) -> String {
+        "CALTRAIND_LOCATION".to_string()
+    }
+
+    fn locate(&self) -> Result<Option<(f64, f64)>, Box<dyn Error>> {
+        match env::var("CALTRAIND_LOCATION") {
+            Ok(text) => Ok(Some(parse_coordinates(&text)?)),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+fn parse_coordinates(text: &str) -> Result<(f64, f64), Box<dyn Error>> {
+    let parts: Vec<&str> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .collect();
+    match parts.as_slice() {
+        [latitude, longitude] => Ok((latitude.parse()?, longitude.parse()?)),
+        _ => Err(format!("expected \"latitude,longitude\", found {:?}", text.trim()).into()),
+    }
+}
+
+/// the station closest to the first provider with a fix
+pub fn locate_station(providers: &[&dyn LocationProvider]) -> Result<Station, Box<dyn Error>> {
+    for provider in providers {
+        let location = provider
+            .locate()
+            .map_err(|e| format!("error reading {}: {}", provider.name(), e))?;
+        if let Some((latitude, longitude)) = location {
+            return Ok(nearest(latitude, longitude)[0].0);
+        }
+    }
+    let tried: Vec<String> = providers.iter().map(|p| p.name()).collect();
+    Err(format!("no location available, tried {}", tried.join(" and ")).into())
+}
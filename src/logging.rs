@@ -0,0 +1,372 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::{metadata, rename, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Local, NaiveDate};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+
+/// how often `logs --follow` checks for new lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// whether the last log line couldn't be written
+static WRITE_FAILED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown log format {:?}, expected text or json", s)),
+        }
+    }
+}
+
+/// which levels to log for which modules, in the `RUST_LOG` syntax of comma separated `level`
+/// and `module::path=level` entries, the longest matching module path wins
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// caltraind's own messages at `level`, only warnings and errors from libraries
+    pub fn verbosity(level: LevelFilter) -> Self {
+        Filter {
+            default: LevelFilter::Warn,
+            modules: vec![("caltraind".to_string(), level)],
+        }
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, cmp::max)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = |level: &str| {
+            level
+                .trim()
+                .parse::<LevelFilter>()
+                .map_err(|_| format!("unknown log level {:?}", level))
+        };
+        let mut filter = Filter {
+            default: LevelFilter::Error,
+            modules: vec![],
+        };
+        for entry in s.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let mut parts = entry.splitn(2, '=');
+            let first = parts.next().unwrap_or_default().trim();
+            match parts.next() {
+                Some(value) => filter.modules.push((first.to_string(), level(value)?)),
+                None => match level(first) {
+                    Ok(level) => filter.default = level,
+                    // a bare module name turns on everything for it
+                    Err(_) => filter.modules.push((first.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// a log file that moves aside to `<name>.1`, `<name>.2`, ... once it reaches `max_size` bytes or
+/// a new day starts, keeping `keep` old files
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: NaiveDate,
+    max_size: u64,
+    keep: usize,
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, keep: usize) -> io::Result<Self> {
+        let file = append(path)?;
+        let metadata = file.metadata()?;
+        let opened = metadata
+            .modified()
+            .map(|modified| {
+                chrono::DateTime::<Local>::from(modified)
+                    .date()
+                    .naive_local()
+            })
+            .unwrap_or_else(|_| Local::today().naive_local());
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size: metadata.len(),
+            opened,
+            max_size,
+            keep,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        if self.keep > 0 {
+            rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .mode(0o600)
+            .open(&self.path)?;
+        self.size = 0;
+        self.opened = Local::today().naive_local();
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let full = self.size > 0 && self.size + len > self.max_size;
+        if full || self.opened != Local::today().naive_local() {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+enum Sink {
+    Stderr,
+    File(RotatingFile),
+}
+
+pub struct Logger {
+    filter: Filter,
+    format: Format,
+    sink: Mutex<Sink>,
+}
+
+impl Logger {
+    fn line(&self, record: &Record) -> String {
+        let time = Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z");
+        match self.format {
+            Format::Text => format!(
+                "{} {:<5} {}: {}",
+                time,
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+            Format::Json => json!({
+                "time": time.to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.line(record);
+        let mut sink = match self.sink.lock() {
+            Ok(sink) => sink,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let result = match &mut *sink {
+            Sink::Stderr => writeln!(io::stderr(), "{}", line),
+            Sink::File(file) => file.write_line(&line),
+        };
+        WRITE_FAILED.store(result.is_err(), Ordering::Relaxed);
+        if let Err(e) = result {
+            eprintln!("error writing log: {}\n{}", e, line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            if let Sink::File(file) = &mut *sink {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+/// where the daemon logs to and how
+pub struct Options {
+    pub filter: Filter,
+    pub format: Format,
+    /// log to stderr for the terminal or journal instead of `path`
+    pub stderr: bool,
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+/// installs the logger, messages logged before this are dropped
+pub fn init(options: Options) -> io::Result<()> {
+    let sink = if options.stderr {
+        Sink::Stderr
+    } else {
+        Sink::File(RotatingFile::open(
+            &options.path,
+            options.max_size,
+            options.keep,
+        )?)
+    };
+    log::set_max_level(options.filter.max());
+    let logger = Logger {
+        filter: options.filter,
+        format: options.format,
+        sink: Mutex::new(sink),
+    };
+    log::set_boxed_logger(Box::new(logger))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+}
+
+/// whether writing the last log line failed
+pub fn failing() -> bool {
+    WRITE_FAILED.load(Ordering::Relaxed)
+}
+
+/// maps `-v` counts onto caltraind's log level
+pub fn level(verbosity: u64) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// prints the last `lines` lines of the log at `path`, then keeps printing new lines across
+/// rotations if `follow` is set, returns the process exit code
+pub fn print(path: &Path, lines: usize, follow: bool) -> i32 {
+    match tail(path, lines, follow) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error reading {}: {}", path.display(), e);
+            1
+        }
+    }
+}
+
+fn tail(path: &Path, lines: usize, follow: bool) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut last = VecDeque::with_capacity(lines);
+    for line in (&mut reader).lines() {
+        let line = line?;
+        if lines == 0 {
+            continue;
+        }
+        if last.len() == lines {
+            last.pop_front();
+        }
+        last.push_back(line);
+    }
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for line in last {
+        writeln!(out, "{}", line)?;
+    }
+    if !follow {
+        return Ok(());
+    }
+    out.flush()?;
+
+    let mut inode = reader.get_ref().metadata()?.ino();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? > 0 {
+            write!(out, "{}", line)?;
+            continue;
+        }
+        out.flush()?;
+        thread::sleep(FOLLOW_INTERVAL);
+        // the daemon moved the file aside and started a new one
+        if let Ok(current) = metadata(path) {
+            if current.ino() != inode {
+                reader = BufReader::new(File::open(path)?);
+                inode = current.ino();
+            } else if current.len() < reader.seek(SeekFrom::Current(0))? {
+                reader.seek(SeekFrom::Start(0))?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filter_picks_most_specific_module() {
+        let filter: Filter = "warn,caltraind=info,caltraind::daemon::notifier=trace"
+            .parse()
+            .unwrap();
+        assert_eq!(filter.level("actix_web::server"), LevelFilter::Warn);
+        assert_eq!(filter.level("caltraind::daemon"), LevelFilter::Info);
+        assert_eq!(
+            filter.level("caltraind::daemon::notifier"),
+            LevelFilter::Trace
+        );
+        assert_eq!(filter.level("caltraindx"), LevelFilter::Warn);
+        assert_eq!(filter.max(), LevelFilter::Trace);
+        assert!("caltraind=loud".parse::<Filter>().is_err());
+    }
+}
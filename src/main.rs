@@ -1,32 +1,178 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
 
-use clap::{crate_authors, crate_description, crate_version, App, AppSettings, Arg, SubCommand};
+use clap::{
+    crate_authors, crate_description, crate_version, App, AppSettings, Arg, ArgMatches, ErrorKind,
+    SubCommand,
+};
+
+use caltraind::{caltrain_status, parse, schedule, station, template, trip};
 
 use crate::caltrain_status::{Direction, TrainType};
-use crate::daemon::close_existing;
-use crate::station::Station;
+use crate::cfg::{Config, LOCATION_PATH};
+use crate::daemon::{
+    close_instance, stop_instance, FetchPolicy, Profile, ReturnTrip, StatusSource, Style,
+    Templates, Travel, TravelMode,
+};
+use crate::location::{locate_station, GeoclueStub, LocationFile};
+use crate::station::{nearest, Station};
+use crate::template::TemplateError;
 use chrono::NaiveTime;
 
-mod caltrain_status;
 pub(crate) mod cfg;
+mod client;
 mod daemon;
-mod station;
+mod location;
+mod logging;
+mod plan;
+
+fn station_long_help() -> String {
+    let stations: Vec<String> = Station::all().map(|s| format!("{:?}", s)).collect();
+    format!(
+        "caltrain station to generate notifications for, or \"auto\" for the station closest to \
+         the location in --location-file or CALTRAIND_LOCATION; \"auto\" is resolved once when \
+         the daemon starts, so run `caltraind start` again after moving to another \
+         office\nvalid stations include: {}",
+        stations.join(", ")
+    )
+}
 
-const STATION_LONG_HELP: &str =
-    "caltrain station to generate notifications for\nvalid stations include: SanFrancisco, \
-     TwentySecondStreet, Bayshore, SouthSanFrancisco, SanBruno, MillbraeTransitCenter, \
-     Broadway, Burlingame, SanMateo, HaywardPark, Hillsdale, Belmont, SanCarlos, RedwoodCity, \
-     Atherton, MenloPark, PaloAlto, CaliforniaAve, SanAntonio, MountainView, Sunnyvale, \
-     Lawrence, SantaClara, CollegePark, SanJoseDiridon, Tamien, Capitol, BlossomHill, \
-     MorganHill, SanMartin, Gilroy";
+/// clap validator for values parsed with `FromStr`, so bad names are reported with suggestions
+/// before anything starts
+fn valid<'a, T>(values: impl IntoIterator<Item = &'a str>) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    for value in values {
+        value.parse::<T>().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// clap validator for `--speed`, zero or negative speeds would replay with no sensible spacing
+fn valid_speed(s: String) -> Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(()),
+        Ok(_) => Err(format!("replay speed must be a number above 0, got {}", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// clap validator for a whole number of seconds, zero would mean polling or giving up right away
+fn valid_seconds(s: String) -> Result<(), String> {
+    match s.parse::<u64>() {
+        Ok(0) => Err("must be at least 1 second".to_string()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// a `start` setting, preferring the command line, then the config file, then the default
+fn setting<'a>(matches: &'a ArgMatches, name: &str, config: Option<&'a String>) -> Option<&'a str> {
+    match config {
+        Some(value) if matches.occurrences_of(name) == 0 => Some(value.as_str()),
+        _ => matches.value_of(name),
+    }
+}
+
+fn parse_or_exit<T>(what: &str, value: &str) -> T
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse().unwrap_or_else(|e| {
+        clap::Error::with_description(&format!("invalid {}: {}", what, e), ErrorKind::InvalidValue)
+            .exit()
+    })
+}
+
+fn missing(flag: &str) -> ! {
+    clap::Error::with_description(
+        &format!(
+            "{} must be given on the command line or in the config file",
+            flag
+        ),
+        ErrorKind::MissingRequiredArgument,
+    )
+    .exit()
+}
+
+/// a global flag, which may have been given before or after the subcommand
+fn global_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    matches
+        .value_of(name)
+        .or_else(|| matches.subcommand().1.and_then(|m| m.value_of(name)))
+}
+
+fn invalid_template(e: TemplateError) -> ! {
+    clap::Error::with_description(&format!("invalid template: {}", e), ErrorKind::InvalidValue)
+        .exit()
+}
+
+/// stops this instance, or every instance with `--all`, reporting how each went, returns the
+/// process exit code
+fn for_instances(
+    matches: &ArgMatches,
+    stop: impl Fn(&Path) -> std::io::Result<daemon::Stopped>,
+) -> i32 {
+    let instances = if matches.is_present("ALL") {
+        match cfg::instances() {
+            Ok(instances) => instances,
+            Err(e) => {
+                eprintln!("unable to list instances: {}", e);
+                return 1;
+            }
+        }
+    } else {
+        vec![(cfg::instance().to_string(), cfg::RUNTIME_PATH.clone())]
+    };
+    let mut code = 0;
+    for (name, runtime_dir) in instances {
+        match stop(&runtime_dir) {
+            Ok(stopped) => println!("{}: {}", name, stopped),
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                code = 1;
+            }
+        }
+    }
+    code
+}
 
 fn main() {
+    let station_long_help = station_long_help();
     let root_matches = App::new("caltraind")
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
+        .arg(Arg::with_name("RUNTIME_DIR")
+            .long("runtime-dir")
+            .takes_value(true)
+            .global(true)
+            .help("directory for the socket and pid file, defaults to $XDG_RUNTIME_DIR/caltraind"))
+        .arg(Arg::with_name("INSTANCE")
+            .short("i")
+            .long("instance")
+            .takes_value(true)
+            .global(true)
+            .validator(|s| cfg::valid_instance(&s))
+            .help("name of the daemon instance to act on, each has its own socket, pid file and logs"))
+        .arg(Arg::with_name("STATE_DIR")
+            .long("state-dir")
+            .takes_value(true)
+            .global(true)
+            .help("directory for logs and saved data, defaults to $XDG_STATE_HOME/caltraind"))
         .subcommand(SubCommand::with_name("start")
+            .arg(Arg::with_name("CONFIG")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help("yaml file of settings keyed by long flag name, defaults to ~/.config/caltraind/config.yaml"))
             .arg(Arg::with_name("THREADS")
                 .short("T")
                 .long("threads")
@@ -38,50 +184,356 @@ fn main() {
                 .long("types")
                 .takes_value(true)
                 .default_value("Local,Limited,BabyBullet")
+                .validator(|s| valid::<TrainType>(s.split_terminator(',')))
                 .help("train types to generate notifications for"))
             .arg(Arg::with_name("STATION")
                 .short("s")
                 .long("station")
                 .takes_value(true)
                 .default_value("PaloAlto")
+                .validator(|s| match s.as_str() {
+                    "auto" => Ok(()),
+                    s => valid::<Station>(Some(s)),
+                })
                 .help("caltrain station to generate notifications for [valid stations in extended help]")
-                .long_help(STATION_LONG_HELP))
+                .long_help(station_long_help.as_str()))
+            .arg(Arg::with_name("DESTINATION")
+                .short("D")
+                .long("destination")
+                .takes_value(true)
+                .validator(|s| valid::<Station>(Some(s.as_str())))
+                .help("station you're headed to, adds estimated arrival times to notifications and status"))
+            .arg(Arg::with_name("LOCATION_FILE")
+                .long("location-file")
+                .takes_value(true)
+                .help("file containing \"latitude,longitude\" used by --station auto, read once at start, defaults to ~/.config/caltraind/location"))
             .arg(Arg::with_name("DIRECTION")
                 .short("d")
                 .long("direction")
                 .takes_value(true)
-                .required(true)
+                .validator(|s| valid::<Direction>(Some(s.as_str())))
                 .help("generate notifications for trains heading in direction [Northbound Southbound]"))
-            .arg(Arg::with_name("REFRESH_RATE")
+            .arg(Arg::with_name("MIN_REFRESH_RATE")
+                .long("min-refresh-rate")
+                .takes_value(true)
+                .default_value("15")
+                .validator(valid_seconds)
+                .help("shortest time in seconds between caltrain queries, used as a train nears a notification"))
+            .arg(Arg::with_name("MAX_REFRESH_RATE")
                 .short("r")
-                .long("refresh-rate")
+                .long("max-refresh-rate")
+                .alias("refresh-rate")
+                .takes_value(true)
+                .default_value("300")
+                .validator(valid_seconds)
+                .help("longest time in seconds between caltrain queries, used when no train is close"))
+            .arg(Arg::with_name("TIMEOUT")
+                .long("timeout")
+                .takes_value(true)
+                .default_value("15")
+                .validator(valid_seconds)
+                .help("seconds to wait for caltrain to respond before giving up on a request"))
+            .arg(Arg::with_name("STALE_AFTER")
+                .long("stale-after")
+                .takes_value(true)
+                .default_value("600")
+                .validator(valid_seconds)
+                .help("seconds without a successful update before warning that the feed is down"))
+            .arg(Arg::with_name("RECORD")
+                .long("record")
+                .takes_value(true)
+                .conflicts_with("REPLAY")
+                .help("save every page fetched from caltrain to this directory"))
+            .arg(Arg::with_name("REPLAY")
+                .long("replay")
                 .takes_value(true)
-                .default_value("60")
-                .help("how often in seconds to query caltrain for updates"))
+                .help("play back pages saved with --record instead of querying caltrain"))
+            .arg(Arg::with_name("SPEED")
+                .long("speed")
+                .takes_value(true)
+                .default_value("1")
+                .validator(valid_speed)
+                .help("how many times faster than real time to play back recorded pages"))
             .arg(Arg::with_name("NOTIFY_AT")
                 .short("n")
                 .long("notify-at")
                 .takes_value(true)
                 .multiple(true)
-                .required(true)
-                .help("number of minutes before train departure to notify"))
+                .help("number of minutes before train departure to notify, or before it's time to leave if --travel-time is set"))
+            .arg(Arg::with_name("TRAVEL_TIME")
+                .long("travel-time")
+                .takes_value(true)
+                .default_value("0")
+                .help("minutes it takes to get to the station, trains departing sooner are not notified"))
+            .arg(Arg::with_name("TRAVEL_MODE")
+                .long("travel-mode")
+                .takes_value(true)
+                .default_value("walk")
+                .validator(|s| valid::<TravelMode>(Some(s.as_str())))
+                .help("how you get to the station [walk bike drive]"))
+            .arg(Arg::with_name("BUFFER")
+                .long("buffer")
+                .takes_value(true)
+                .default_value("0")
+                .help("extra minutes to arrive at the station before departure"))
             .arg(Arg::with_name("NOTIFY_AFTER")
                 .short("A")
                 .long("notify-after")
                 .takes_value(true)
                 .help("only display notifications after this time, 24h format (eg. 14:50)"))
+            .arg(Arg::with_name("RETURN_AFTER")
+                .long("return-after")
+                .takes_value(true)
+                .help("suggest trains back from --destination after this time on days a train was taken before it, 24h format (eg. 16:30)"))
+            .arg(Arg::with_name("LAST_TRAIN")
+                .long("last-train")
+                .takes_value(true)
+                .help("time of the last reasonable train back from --destination, 24h format (eg. 21:45)"))
+            .arg(Arg::with_name("LAST_TRAIN_WARNING")
+                .long("last-train-warning")
+                .takes_value(true)
+                .default_value("30")
+                .help("minutes before --last-train to warn"))
+            .arg(Arg::with_name("COUNTDOWN")
+                .long("countdown")
+                .help("keep each train's notification counting down until it departs"))
+            .arg(Arg::with_name("FOREGROUND")
+                .long("foreground")
+                .help("don't fork into the background, log to stderr and tell systemd when ready"))
+            .arg(Arg::with_name("METRICS_ADDR")
+                .long("metrics-addr")
+                .takes_value(true)
+                .validator(|s| valid::<SocketAddr>(Some(s.as_str())))
+                .help("also serve prometheus metrics on this tcp address (eg. 127.0.0.1:9898), they're always on the control socket at /metrics"))
+            .arg(Arg::with_name("VERBOSE")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("log fetches and notification decisions, twice for everything, RUST_LOG takes precedence"))
+            .arg(Arg::with_name("LOG_FORMAT")
+                .long("log-format")
+                .takes_value(true)
+                .default_value("text")
+                .validator(|s| valid::<logging::Format>(Some(s.as_str())))
+                .help("text or json, one line per message"))
+            .arg(Arg::with_name("LOG_MAX_SIZE")
+                .long("log-max-size")
+                .takes_value(true)
+                .default_value("10")
+                .help("megabytes the log grows to before it's rotated, it's also rotated daily"))
+            .arg(Arg::with_name("LOG_KEEP")
+                .long("log-keep")
+                .takes_value(true)
+                .default_value("5")
+                .help("number of rotated logs to keep"))
             .about("start the notifier daemon"))
+        .subcommand(SubCommand::with_name("install-systemd-unit")
+            .arg(Arg::with_name("SOCKET_ACTIVATION")
+                .long("socket-activation")
+                .help("also write a socket unit so systemd owns the control socket and starts the daemon on first use"))
+            .arg(Arg::with_name("UNIT_DIR")
+                .long("unit-dir")
+                .takes_value(true)
+                .help("directory to write units to, defaults to ~/.config/systemd/user"))
+            .arg(Arg::with_name("START_ARGS")
+                .multiple(true)
+                .last(true)
+                .help("arguments for `start`, given after --"))
+            .about("write a systemd user unit that runs the daemon in the foreground"))
         .subcommand(SubCommand::with_name("kill")
-            .about("kill existing daemon instance"))
+            .arg(Arg::with_name("ALL")
+                .long("all")
+                .help("kill every instance"))
+            .about("kill existing daemon instance, without waiting long for it to save its state"))
+        .subcommand(SubCommand::with_name("stop")
+            .arg(Arg::with_name("ALL")
+                .long("all")
+                .help("stop every instance"))
+            .arg(Arg::with_name("TIMEOUT")
+                .long("timeout")
+                .takes_value(true)
+                .default_value("30")
+                .help("seconds to wait for the daemon to save its state and exit"))
+            .about("ask the daemon to shut down cleanly and wait until it has"))
+        .subcommand(SubCommand::with_name("logs")
+            .arg(Arg::with_name("FOLLOW")
+                .short("f")
+                .long("follow")
+                .help("keep printing new lines as they're logged"))
+            .arg(Arg::with_name("LINES")
+                .short("n")
+                .long("lines")
+                .takes_value(true)
+                .default_value("20")
+                .help("number of lines to print from the end of the log"))
+            .about("print the daemon's log, daemons started with --foreground log to stderr instead"))
+        .subcommand(SubCommand::with_name("list")
+            .about("list daemon instances, whether they're running and how they were started"))
+        .subcommand(SubCommand::with_name("nearest")
+            .arg(Arg::with_name("LAT")
+                .long("lat")
+                .takes_value(true)
+                .required(true)
+                .allow_hyphen_values(true)
+                .help("latitude in decimal degrees"))
+            .arg(Arg::with_name("LON")
+                .long("lon")
+                .takes_value(true)
+                .required(true)
+                .allow_hyphen_values(true)
+                .help("longitude in decimal degrees, negative for west"))
+            .arg(Arg::with_name("COUNT")
+                .short("n")
+                .long("count")
+                .takes_value(true)
+                .default_value("3")
+                .help("number of stations to list"))
+            .about("list the stations closest to a location"))
+        .subcommand(SubCommand::with_name("plan")
+            .arg(Arg::with_name("FROM")
+                .short("f")
+                .long("from")
+                .takes_value(true)
+                .required(true)
+                .validator(|s| valid::<Station>(Some(s.as_str())))
+                .help("station to leave from"))
+            .arg(Arg::with_name("TO")
+                .long("to")
+                .takes_value(true)
+                .required(true)
+                .validator(|s| valid::<Station>(Some(s.as_str())))
+                .help("station to go to"))
+            .arg(Arg::with_name("DEPART_AFTER")
+                .short("a")
+                .long("depart-after")
+                .takes_value(true)
+                .help("only consider trains leaving after this time, 24h format (eg. 14:50)"))
+            .arg(Arg::with_name("TIMEOUT")
+                .long("timeout")
+                .takes_value(true)
+                .default_value("15")
+                .help("seconds to wait for caltrain to respond"))
+            .about("list direct and single transfer trips between two stations, earliest arrival first"))
+        .subcommand(SubCommand::with_name("action")
+            .arg(Arg::with_name("TRAIN")
+                .required(true)
+                .help("train number, as shown in the notification"))
+            .arg(Arg::with_name("ACTION")
+                .required(true)
+                .possible_values(&["snooze", "skip", "take"])
+                .help("snooze notifications for 5 minutes, skip the train, or say you're taking it"))
+            .about("act on a train the way the notification buttons do"))
+        .subcommand(SubCommand::with_name("status")
+            .about("show the last status fetched by the daemon, exits non-zero if fetching is failing"))
+        .subcommand(SubCommand::with_name("health")
+            .about("check the daemon fetched recently, parsed the last page and can reach every sink, exits non-zero with the reason if not"))
         .setting(AppSettings::SubcommandRequired)
         .get_matches();
 
-    if root_matches.subcommand_matches("kill").is_some() {
-        close_existing();
+    let start_matches = root_matches.subcommand_matches("start");
+    let config = Config::load(
+        start_matches
+            .and_then(|m| m.value_of("CONFIG"))
+            .map(Path::new),
+    )
+    .unwrap_or_else(|e| clap::Error::with_description(&e.to_string(), ErrorKind::Io).exit());
+    cfg::init_dirs(
+        global_value(&root_matches, "RUNTIME_DIR").map(PathBuf::from),
+        global_value(&root_matches, "STATE_DIR").map(PathBuf::from),
+        global_value(&root_matches, "INSTANCE").map(String::from),
+        &config,
+    )
+    .unwrap_or_else(|e| clap::Error::with_description(&e, ErrorKind::InvalidValue).exit());
+
+    if let Some(matches) = root_matches.subcommand_matches("kill") {
+        std::process::exit(for_instances(matches, close_instance));
+    }
+
+    if let Some(matches) = root_matches.subcommand_matches("stop") {
+        let timeout = Duration::from_secs(parse_or_exit(
+            "timeout",
+            matches.value_of("TIMEOUT").unwrap(),
+        ));
+        std::process::exit(for_instances(matches, |runtime_dir| {
+            stop_instance(runtime_dir, timeout)
+        }));
+    }
+
+    if let Some(matches) = root_matches.subcommand_matches("install-systemd-unit") {
+        // the unit's daemon has to find the same directories as the clients started from here
+        let mut global_args = vec![];
+        for (name, flag) in &[
+            ("RUNTIME_DIR", "--runtime-dir"),
+            ("STATE_DIR", "--state-dir"),
+        ] {
+            if let Some(value) = global_value(&root_matches, name) {
+                global_args.push(flag.to_string());
+                global_args.push(value.to_string());
+            }
+        }
+        let start_args: Vec<String> = matches
+            .values_of("START_ARGS")
+            .map_or(vec![], |args| args.map(String::from).collect());
+        std::process::exit(daemon::systemd::install(
+            matches.value_of("UNIT_DIR").map(PathBuf::from),
+            &global_args,
+            &start_args,
+            matches.is_present("SOCKET_ACTIVATION"),
+        ));
+    }
+
+    if let Some(matches) = root_matches.subcommand_matches("logs") {
+        let lines: usize = parse_or_exit("line count", matches.value_of("LINES").unwrap());
+        std::process::exit(logging::print(
+            &cfg::LOG_PATH,
+            lines,
+            matches.is_present("FOLLOW"),
+        ));
+    }
+
+    if root_matches.subcommand_matches("list").is_some() {
+        std::process::exit(client::list());
+    }
+
+    if root_matches.subcommand_matches("health").is_some() {
+        std::process::exit(client::health());
+    }
+
+    if root_matches.subcommand_matches("status").is_some() {
+        std::process::exit(client::status());
+    }
+
+    if let Some(matches) = root_matches.subcommand_matches("action") {
+        let train: u16 = parse_or_exit("train", matches.value_of("TRAIN").unwrap());
+        std::process::exit(client::action(train, matches.value_of("ACTION").unwrap()));
+    }
+
+    if let Some(matches) = root_matches.subcommand_matches("nearest") {
+        let latitude: f64 = parse_or_exit("latitude", matches.value_of("LAT").unwrap());
+        let longitude: f64 = parse_or_exit("longitude", matches.value_of("LON").unwrap());
+        let count: usize = parse_or_exit("count", matches.value_of("COUNT").unwrap());
+        for (station, km) in nearest(latitude, longitude).into_iter().take(count) {
+            println!("{:>6.2} km  {} (zone {})", km, station, station.info().zone);
+        }
         return;
     }
 
-    let matches = root_matches.subcommand_matches("start").unwrap();
+    if let Some(matches) = root_matches.subcommand_matches("plan") {
+        let from: Station = parse_or_exit("station", matches.value_of("FROM").unwrap());
+        let to: Station = parse_or_exit("station", matches.value_of("TO").unwrap());
+        let depart_after = matches.value_of("DEPART_AFTER").map_or(0, |s| {
+            plan::minutes_until(
+                NaiveTime::parse_from_str(s, "%k:%M").expect("invalid depart after time"),
+            )
+        });
+        let timeout = Duration::from_secs(parse_or_exit(
+            "timeout",
+            matches.value_of("TIMEOUT").unwrap(),
+        ));
+        std::process::exit(plan::plan(from, to, depart_after, timeout));
+    }
+
+    let matches = start_matches.unwrap();
 
     let n_threads: usize = matches
         .value_of("THREADS")
@@ -89,46 +541,211 @@ fn main() {
         .parse()
         .expect("error while parsing number of threads");
 
-    let train_types: BTreeSet<TrainType> = matches
-        .values_of("TYPES")
-        .unwrap()
-        .map(|t| t.split_terminator(','))
-        .flatten()
-        .map(|t| serde_yaml::from_str(t).expect("error parsing train type"))
-        .collect();
+    let train_types: BTreeSet<TrainType> = match (&config.types, matches.occurrences_of("TYPES")) {
+        (Some(types), 0) => types
+            .iter()
+            .map(|t| parse_or_exit("train type", t))
+            .collect(),
+        _ => matches
+            .values_of("TYPES")
+            .unwrap()
+            .map(|t| t.split_terminator(','))
+            .flatten()
+            .map(|t| t.parse().unwrap())
+            .collect(),
+    };
 
-    let station: Station =
-        serde_yaml::from_str(matches.value_of("STATION").unwrap()).expect("error parsing station");
+    let station: Station = match setting(matches, "STATION", config.station.as_ref()).unwrap() {
+        "auto" => {
+            let location_file = matches
+                .value_of("LOCATION_FILE")
+                .map(PathBuf::from)
+                .or_else(|| config.location_file.clone())
+                .unwrap_or_else(|| LOCATION_PATH.clone());
+            let station = locate_station(&[&LocationFile(location_file), &GeoclueStub])
+                .unwrap_or_else(|e| {
+                    clap::Error::with_description(
+                        &format!("unable to pick a station automatically: {}", e),
+                        ErrorKind::InvalidValue,
+                    )
+                    .exit()
+                });
+            println!(
+                "using closest station, {}, restart caltraind to pick again after moving",
+                station
+            );
+            station
+        }
+        s => parse_or_exit("station", s),
+    };
 
-    let direction: Direction = serde_yaml::from_str(matches.value_of("DIRECTION").unwrap())
-        .expect("error parsing direction");
+    let direction: Direction = match setting(matches, "DIRECTION", config.direction.as_ref()) {
+        Some(d) => parse_or_exit("direction", d),
+        None => missing("--direction"),
+    };
 
-    let refresh_rate = Duration::from_secs(
-        matches
-            .value_of("REFRESH_RATE")
-            .unwrap()
-            .parse()
-            .expect("error parsing refresh rate"),
-    );
+    let min_refresh_rate = Duration::from_secs(parse_or_exit(
+        "min refresh rate",
+        matches.value_of("MIN_REFRESH_RATE").unwrap(),
+    ));
+    let max_refresh_rate = Duration::from_secs(parse_or_exit(
+        "max refresh rate",
+        matches.value_of("MAX_REFRESH_RATE").unwrap(),
+    ));
+    if min_refresh_rate > max_refresh_rate {
+        clap::Error::with_description(
+            "--min-refresh-rate can't be longer than --max-refresh-rate",
+            ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
 
-    let notify_at: Vec<u16> = matches
-        .values_of("NOTIFY_AT")
-        .unwrap()
-        .map(|n| n.parse().expect("invalid notification time"))
-        .collect();
+    let timeout = Duration::from_secs(parse_or_exit(
+        "timeout",
+        matches.value_of("TIMEOUT").unwrap(),
+    ));
+    let stale_after = Duration::from_secs(parse_or_exit(
+        "stale after",
+        matches.value_of("STALE_AFTER").unwrap(),
+    ));
+
+    let record_to = matches.value_of("RECORD").map(|dir| {
+        std::fs::create_dir_all(dir).expect("error creating record directory");
+        std::fs::canonicalize(dir).expect("invalid record directory")
+    });
+
+    let source = match matches.value_of("REPLAY") {
+        Some(dir) => StatusSource::Replay {
+            dir: std::fs::canonicalize(dir).expect("invalid replay directory"),
+            speed: matches
+                .value_of("SPEED")
+                .unwrap()
+                .parse()
+                .expect("error parsing replay speed"),
+        },
+        None => StatusSource::Live(FetchPolicy {
+            min_refresh_rate,
+            max_refresh_rate,
+            timeout,
+            stale_after,
+            record_to,
+        }),
+    };
 
-    let notify_after = matches
-        .value_of("NOTIFY_AFTER")
+    let notify_at: Vec<u16> = match (matches.values_of("NOTIFY_AT"), &config.notify_at) {
+        (Some(values), _) => values
+            .map(|n| parse_or_exit("notification time", n))
+            .collect(),
+        (None, Some(notify_at)) => notify_at.clone(),
+        (None, None) => missing("--notify-at"),
+    };
+
+    let notify_after = setting(matches, "NOTIFY_AFTER", config.notify_after.as_ref())
         .map(|s| NaiveTime::parse_from_str(s, "%k:%M").expect("invalid notify after time"));
 
-    daemon::start(
-        n_threads,
-        train_types,
+    let travel_time = config.travel_time.map(|m| m.to_string());
+    let buffer = config.buffer.map(|m| m.to_string());
+    let travel = Travel {
+        minutes: parse_or_exit(
+            "travel time",
+            setting(matches, "TRAVEL_TIME", travel_time.as_ref()).unwrap(),
+        ),
+        mode: parse_or_exit(
+            "travel mode",
+            setting(matches, "TRAVEL_MODE", config.travel_mode.as_ref()).unwrap(),
+        ),
+        buffer: parse_or_exit(
+            "buffer",
+            setting(matches, "BUFFER", buffer.as_ref()).unwrap(),
+        ),
+    };
+
+    let destination: Option<Station> = setting(matches, "DESTINATION", config.destination.as_ref())
+        .map(|s| parse_or_exit("destination", s));
+
+    let styles: BTreeMap<TrainType, Style> = config
+        .styles
+        .iter()
+        .map(|(ttype, style)| {
+            let style = Style {
+                urgency: style.urgency.as_ref().map(|u| parse_or_exit("urgency", u)),
+                icon: style.icon.clone(),
+            };
+            (parse_or_exit("train type", ttype), style)
+        })
+        .collect();
+    let templates = Templates::departure(&config.templates, travel, styles.clone())
+        .unwrap_or_else(|e| invalid_template(e));
+
+    let return_after = setting(matches, "RETURN_AFTER", config.return_after.as_ref())
+        .map(|s| NaiveTime::parse_from_str(s, "%k:%M").expect("invalid return after time"));
+    let last_train = setting(matches, "LAST_TRAIN", config.last_train.as_ref())
+        .map(|s| NaiveTime::parse_from_str(s, "%k:%M").expect("invalid last train time"));
+    let last_train_warning = config.last_train_warning.map(|m| m.to_string());
+    let return_trip = match (return_after, last_train) {
+        (None, None) => None,
+        _ if destination.is_none() => missing("--destination"),
+        (after, last_train) => Some(ReturnTrip {
+            // without an explicit time, anything taken before noon is the morning commute
+            after: after.unwrap_or_else(|| NaiveTime::from_hms(12, 0, 0)),
+            last_train,
+            last_train_warning: parse_or_exit(
+                "last train warning",
+                setting(matches, "LAST_TRAIN_WARNING", last_train_warning.as_ref()).unwrap(),
+            ),
+            templates: Templates::return_trip(&config.return_templates, styles)
+                .unwrap_or_else(|e| invalid_template(e)),
+        }),
+    };
+
+    let profile = Profile {
         station,
         direction,
-        refresh_rate,
+        train_types,
         notify_at,
         notify_after,
-    )
-    .unwrap();
+        travel,
+        destination,
+        return_trip,
+        templates,
+        countdown: matches.is_present("COUNTDOWN") || config.countdown.unwrap_or(false),
+    };
+
+    let foreground = matches.is_present("FOREGROUND") || config.foreground.unwrap_or(false);
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(spec) => parse_or_exit("RUST_LOG", &spec),
+        Err(_) => logging::Filter::verbosity(logging::level(matches.occurrences_of("VERBOSE"))),
+    };
+    let log_max_size = config.log_max_size.map(|m| m.to_string());
+    let log_max_size: u64 = parse_or_exit(
+        "log max size",
+        setting(matches, "LOG_MAX_SIZE", log_max_size.as_ref()).unwrap(),
+    );
+    let log_keep = config.log_keep.map(|n| n.to_string());
+    let log_options = logging::Options {
+        filter,
+        format: parse_or_exit(
+            "log format",
+            setting(matches, "LOG_FORMAT", config.log_format.as_ref()).unwrap(),
+        ),
+        stderr: foreground,
+        path: cfg::LOG_PATH.clone(),
+        max_size: log_max_size * 1024 * 1024,
+        keep: parse_or_exit(
+            "log keep",
+            setting(matches, "LOG_KEEP", log_keep.as_ref()).unwrap(),
+        ),
+    };
+    if let Err(e) = logging::init(log_options) {
+        clap::Error::with_description(
+            &format!("error opening {}: {}", cfg::LOG_PATH.display(), e),
+            ErrorKind::Io,
+        )
+        .exit();
+    }
+
+    let metrics_addr = setting(matches, "METRICS_ADDR", config.metrics_addr.as_ref())
+        .map(|addr| parse_or_exit("metrics address", addr));
+
+    daemon::start(n_threads, profile, source, foreground, metrics_addr).unwrap();
 }
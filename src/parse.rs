@@ -0,0 +1,95 @@
+use std::fmt;
+
+/// error for a name that didn't match any known value, with the closest matches
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    what: &'static str,
+    input: String,
+    suggestions: Vec<String>,
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown {} {:?}", self.what, self.input)?;
+        if !self.suggestions.is_empty() {
+            write!(f, ", did you mean {}?", self.suggestions.join(" or "))?;
+        }
+        Ok(())
+    }
+}
+
+/// lowercases and drops everything but letters and digits, so "Palo Alto", "palo-alto" and
+/// "PaloAlto" all compare equal
+pub fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// matches `input` against every key of every candidate after normalizing both, on failure
+/// suggests the display names of up to three candidates with a key within a few edits
+pub fn parse_named<T, I>(what: &'static str, input: &str, candidates: I) -> Result<T, ParseError>
+where
+    T: Copy,
+    I: IntoIterator<Item = (T, String, Vec<String>)>,
+{
+    let needle = normalize(input);
+    let mut close = vec![];
+    for (value, display, keys) in candidates {
+        let distance = keys
+            .iter()
+            .map(|key| edit_distance(&needle, &normalize(key)))
+            .min();
+        let distance = match distance {
+            Some(distance) => distance,
+            None => continue,
+        };
+        if distance == 0 {
+            return Ok(value);
+        }
+        if distance <= (needle.len() / 3).max(2) {
+            close.push((distance, display));
+        }
+    }
+    close.sort();
+    Err(ParseError {
+        what,
+        input: input.to_string(),
+        suggestions: close
+            .into_iter()
+            .take(3)
+            .map(|(_, display)| display)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_edits() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("paloalto", "paloalto"), 0);
+        assert_eq!(edit_distance("palaalto", "paloalto"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use chrono::{Local, NaiveTime};
+
+use crate::daemon::fetch_once;
+use crate::station::Station;
+use crate::trip::{self, Trip};
+
+/// minutes from now until `time` today, or zero if it has already passed
+pub fn minutes_until(time: NaiveTime) -> u16 {
+    let minutes = (time - Local::now().time()).num_minutes();
+    if minutes <= 0 {
+        0
+    } else {
+        minutes.min(i64::from(u16::max_value())) as u16
+    }
+}
+
+/// prints direct and single transfer trips from `from` to `to`, returns the process exit code
+pub fn plan(from: Station, to: Station, depart_after: u16, timeout: Duration) -> i32 {
+    let mut stations = vec![from];
+    stations.extend(trip::transfer_stations(from, to));
+    let fetched = match fetch_once(stations, timeout) {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            eprintln!("unable to fetch caltrain status: {}", e);
+            return 1;
+        }
+    };
+
+    let mut live = vec![];
+    for (station, result) in fetched {
+        match result {
+            Ok(status) => live.push((station, status)),
+            Err(e) if station == from => {
+                eprintln!(
+                    "unable to fetch status for {} [{}]: {}",
+                    station,
+                    e.kind(),
+                    e
+                );
+                return e.exit_code();
+            }
+            Err(e) => eprintln!(
+                "estimating departures at {} from the schedule, fetching its status failed [{}]: {}",
+                station,
+                e.kind(),
+                e
+            ),
+        }
+    }
+
+    let trips = trip::plan(from, to, depart_after, &live);
+    if trips.is_empty() {
+        println!("no upcoming trains from {} reach {}", from, to);
+    }
+    for trip in &trips {
+        print_trip(trip);
+    }
+    0
+}
+
+fn clock(minutes: u16) -> String {
+    let at = Local::now() + chrono::Duration::minutes(i64::from(minutes));
+    at.format("%l:%M%p").to_string().trim().to_string()
+}
+
+fn print_trip(trip: &Trip) {
+    let transfers = match trip.legs.len() {
+        1 => "direct".to_string(),
+        2 => "1 transfer".to_string(),
+        n => format!("{} transfers", n - 1),
+    };
+    println!(
+        "arrive {} ({} min, {})",
+        clock(trip.arrives_in()),
+        trip.arrives_in() - trip.departs_in(),
+        transfers
+    );
+    for leg in &trip.legs {
+        println!(
+            "  {} {}  {} {}{} -> {} {}",
+            leg.train_type,
+            leg.train,
+            leg.board,
+            clock(leg.departs_in),
+            if leg.scheduled { " (scheduled)" } else { "" },
+            leg.alight,
+            clock(leg.arrives_in)
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::caltrain_status::{Direction, IncomingTrain, TrainType, NO_COUNTDOWN};
+use crate::station::Station;
+
+// typical minutes from San Francisco to each station by train type, indexed by
+// `Station::order`, `None` where that type usually doesn't stop
+
+static LOCAL: [Option<u16>; 31] = [
+    Some(0),   // SanFrancisco
+    Some(4),   // TwentySecondStreet
+    Some(9),   // Bayshore
+    Some(14),  // SouthSanFrancisco
+    Some(18),  // SanBruno
+    Some(22),  // MillbraeTransitCenter
+    Some(25),  // Broadway
+    Some(28),  // Burlingame
+    Some(31),  // SanMateo
+    Some(34),  // HaywardPark
+    Some(37),  // Hillsdale
+    Some(40),  // Belmont
+    Some(43),  // SanCarlos
+    Some(47),  // RedwoodCity
+    Some(51),  // Atherton
+    Some(54),  // MenloPark
+    Some(57),  // PaloAlto
+    Some(60),  // CaliforniaAve
+    Some(64),  // SanAntonio
+    Some(67),  // MountainView
+    Some(72),  // Sunnyvale
+    Some(76),  // Lawrence
+    Some(81),  // SantaClara
+    Some(84),  // CollegePark
+    Some(88),  // SanJoseDiridon
+    Some(93),  // Tamien
+    Some(100), // Capitol
+    Some(106), // BlossomHill
+    Some(118), // MorganHill
+    Some(124), // SanMartin
+    Some(133), // Gilroy
+];
+
+static LIMITED: [Option<u16>; 31] = [
+    Some(0),  // SanFrancisco
+    Some(4),  // TwentySecondStreet
+    None,     // Bayshore
+    Some(12), // SouthSanFrancisco
+    Some(15), // SanBruno
+    Some(19), // MillbraeTransitCenter
+    None,     // Broadway
+    Some(23), // Burlingame
+    Some(26), // SanMateo
+    Some(29), // HaywardPark
+    Some(32), // Hillsdale
+    Some(35), // Belmont
+    Some(38), // SanCarlos
+    Some(42), // RedwoodCity
+    None,     // Atherton
+    Some(47), // MenloPark
+    Some(50), // PaloAlto
+    Some(53), // CaliforniaAve
+    Some(56), // SanAntonio
+    Some(59), // MountainView
+    Some(64), // Sunnyvale
+    Some(68), // Lawrence
+    Some(73), // SantaClara
+    None,     // CollegePark
+    Some(79), // SanJoseDiridon
+    Some(84), // Tamien
+    None,     // Capitol
+    None,     // BlossomHill
+    None,     // MorganHill
+    None,     // SanMartin
+    None,     // Gilroy
+];
+
+static BABY_BULLET: [Option<u16>; 31] = [
+    Some(0),  // SanFrancisco
+    Some(4),  // TwentySecondStreet
+    None,     // Bayshore
+    None,     // SouthSanFrancisco
+    None,     // SanBruno
+    Some(15), // MillbraeTransitCenter
+    None,     // Broadway
+    None,     // Burlingame
+    Some(21), // SanMateo
+    None,     // HaywardPark
+    Some(24), // Hillsdale
+    None,     // Belmont
+    None,     // SanCarlos
+    Some(30), // RedwoodCity
+    None,     // Atherton
+    Some(35), // MenloPark
+    Some(38), // PaloAlto
+    None,     // CaliforniaAve
+    None,     // SanAntonio
+    Some(44), // MountainView
+    Some(49), // Sunnyvale
+    None,     // Lawrence
+    None,     // SantaClara
+    None,     // CollegePark
+    Some(60), // SanJoseDiridon
+    Some(65), // Tamien
+    None,     // Capitol
+    None,     // BlossomHill
+    None,     // MorganHill
+    None,     // SanMartin
+    None,     // Gilroy
+];
+
+fn minutes_from_sf(ttype: TrainType, station: Station) -> Option<u16> {
+    let table = match ttype {
+        TrainType::Local => &LOCAL,
+        TrainType::Limited => &LIMITED,
+        TrainType::BabyBullet => &BABY_BULLET,
+    };
+    table[station.order()]
+}
+
+/// the direction a train has to be heading to get from one station to another
+pub fn direction_between(from: Station, to: Station) -> Option<Direction> {
+    if to.order() < from.order() {
+        Some(Direction::Northbound)
+    } else if to.order() > from.order() {
+        Some(Direction::Southbound)
+    } else {
+        None
+    }
+}
+
+/// typical minutes a train of `ttype` takes between two stations, `None` if that type doesn't
+/// usually stop at both
+pub fn run_minutes(ttype: TrainType, from: Station, to: Station) -> Option<u16> {
+    let from = minutes_from_sf(ttype, from)?;
+    let to = minutes_from_sf(ttype, to)?;
+    Some(from.max(to) - from.min(to))
+}
+
+/// minutes until `train`, currently listed at `from` heading `direction`, reaches `to`
+///
+/// the live departure estimate already includes any delay at `from`, so that delay carries
+/// through to the arrival; `None` when the train has no countdown to go by
+pub fn minutes_to_arrival(
+    train: &IncomingTrain,
+    direction: Direction,
+    from: Station,
+    to: Station,
+) -> Option<u16> {
+    if direction_between(from, to) != Some(direction)
+        || train.get_min_till_departure() == NO_COUNTDOWN
+    {
+        return None;
+    }
+    train
+        .get_min_till_departure()
+        .checked_add(run_minutes(train.get_train_type(), from, to)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_minutes_by_type() {
+        let pa = Station::PaloAlto;
+        let sf = Station::SanFrancisco;
+        assert_eq!(run_minutes(TrainType::Local, pa, sf), Some(57));
+        assert_eq!(run_minutes(TrainType::Local, sf, pa), Some(57));
+        assert_eq!(run_minutes(TrainType::BabyBullet, pa, sf), Some(38));
+        assert_eq!(
+            run_minutes(TrainType::BabyBullet, pa, Station::CaliforniaAve),
+            None
+        );
+    }
+
+    #[test]
+    fn arrival_needs_matching_direction() {
+        assert_eq!(
+            direction_between(Station::PaloAlto, Station::SanFrancisco),
+            Some(Direction::Northbound)
+        );
+        assert_eq!(
+            direction_between(Station::PaloAlto, Station::PaloAlto),
+            None
+        );
+    }
+
+    #[test]
+    fn arrival_needs_a_countdown() {
+        let pa = Station::PaloAlto;
+        let sf = Station::SanFrancisco;
+        let on_time = IncomingTrain::new(101, TrainType::Local, 4);
+        let unknown = IncomingTrain::new(103, TrainType::Local, NO_COUNTDOWN);
+        assert_eq!(
+            minutes_to_arrival(&on_time, Direction::Northbound, pa, sf),
+            Some(4 + 57)
+        );
+        assert_eq!(
+            minutes_to_arrival(&unknown, Direction::Northbound, pa, sf),
+            None
+        );
+    }
+}
@@ -1,5 +1,11 @@
+use std::fmt;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 
+use crate::parse::{parse_named, ParseError};
+
+/// stations in line order, from San Francisco to Gilroy
 #[derive(Serialize, Deserialize, Clone, Copy, PartialOrd, Ord, Eq, PartialEq, Debug)]
 pub enum Station {
     SanFrancisco,
@@ -35,7 +41,365 @@ pub enum Station {
     Gilroy,
 }
 
+#[derive(Debug)]
+pub struct StationInfo {
+    pub station: Station,
+    /// name as printed on caltrain signage
+    pub name: &'static str,
+    /// other names riders commonly use
+    pub aliases: &'static [&'static str],
+    pub zone: u8,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// gtfs stop id of the platform served by northbound trains
+    pub northbound_stop_id: u32,
+    /// gtfs stop id of the platform served by southbound trains
+    pub southbound_stop_id: u32,
+}
+
+/// indexed by `Station as usize`
+static STATIONS: [StationInfo; 31] = [
+    StationInfo {
+        station: Station::SanFrancisco,
+        name: "San Francisco",
+        aliases: &["SF", "4th and King"],
+        zone: 1,
+        latitude: 37.7766,
+        longitude: -122.3947,
+        northbound_stop_id: 70011,
+        southbound_stop_id: 70012,
+    },
+    StationInfo {
+        station: Station::TwentySecondStreet,
+        name: "22nd Street",
+        aliases: &["22nd"],
+        zone: 1,
+        latitude: 37.7574,
+        longitude: -122.3924,
+        northbound_stop_id: 70021,
+        southbound_stop_id: 70022,
+    },
+    StationInfo {
+        station: Station::Bayshore,
+        name: "Bayshore",
+        aliases: &[],
+        zone: 1,
+        latitude: 37.7097,
+        longitude: -122.4014,
+        northbound_stop_id: 70031,
+        southbound_stop_id: 70032,
+    },
+    StationInfo {
+        station: Station::SouthSanFrancisco,
+        name: "South San Francisco",
+        aliases: &["SSF"],
+        zone: 1,
+        latitude: 37.6576,
+        longitude: -122.4056,
+        northbound_stop_id: 70041,
+        southbound_stop_id: 70042,
+    },
+    StationInfo {
+        station: Station::SanBruno,
+        name: "San Bruno",
+        aliases: &[],
+        zone: 1,
+        latitude: 37.6301,
+        longitude: -122.4113,
+        northbound_stop_id: 70051,
+        southbound_stop_id: 70052,
+    },
+    StationInfo {
+        station: Station::MillbraeTransitCenter,
+        name: "Millbrae Transit Center",
+        aliases: &["Millbrae"],
+        zone: 2,
+        latitude: 37.6003,
+        longitude: -122.3867,
+        northbound_stop_id: 70061,
+        southbound_stop_id: 70062,
+    },
+    StationInfo {
+        station: Station::Broadway,
+        name: "Broadway",
+        aliases: &[],
+        zone: 2,
+        latitude: 37.5874,
+        longitude: -122.3627,
+        northbound_stop_id: 70071,
+        southbound_stop_id: 70072,
+    },
+    StationInfo {
+        station: Station::Burlingame,
+        name: "Burlingame",
+        aliases: &[],
+        zone: 2,
+        latitude: 37.5796,
+        longitude: -122.345,
+        northbound_stop_id: 70081,
+        southbound_stop_id: 70082,
+    },
+    StationInfo {
+        station: Station::SanMateo,
+        name: "San Mateo",
+        aliases: &[],
+        zone: 2,
+        latitude: 37.568,
+        longitude: -122.3239,
+        northbound_stop_id: 70091,
+        southbound_stop_id: 70092,
+    },
+    StationInfo {
+        station: Station::HaywardPark,
+        name: "Hayward Park",
+        aliases: &[],
+        zone: 2,
+        latitude: 37.5525,
+        longitude: -122.309,
+        northbound_stop_id: 70101,
+        southbound_stop_id: 70102,
+    },
+    StationInfo {
+        station: Station::Hillsdale,
+        name: "Hillsdale",
+        aliases: &[],
+        zone: 2,
+        latitude: 37.5379,
+        longitude: -122.2973,
+        northbound_stop_id: 70111,
+        southbound_stop_id: 70112,
+    },
+    StationInfo {
+        station: Station::Belmont,
+        name: "Belmont",
+        aliases: &[],
+        zone: 2,
+        latitude: 37.5209,
+        longitude: -122.2759,
+        northbound_stop_id: 70121,
+        southbound_stop_id: 70122,
+    },
+    StationInfo {
+        station: Station::SanCarlos,
+        name: "San Carlos",
+        aliases: &[],
+        zone: 2,
+        latitude: 37.5076,
+        longitude: -122.26,
+        northbound_stop_id: 70131,
+        southbound_stop_id: 70132,
+    },
+    StationInfo {
+        station: Station::RedwoodCity,
+        name: "Redwood City",
+        aliases: &["RWC"],
+        zone: 2,
+        latitude: 37.4855,
+        longitude: -122.2319,
+        northbound_stop_id: 70141,
+        southbound_stop_id: 70142,
+    },
+    StationInfo {
+        station: Station::Atherton,
+        name: "Atherton",
+        aliases: &[],
+        zone: 3,
+        latitude: 37.4644,
+        longitude: -122.1975,
+        northbound_stop_id: 70151,
+        southbound_stop_id: 70152,
+    },
+    StationInfo {
+        station: Station::MenloPark,
+        name: "Menlo Park",
+        aliases: &["Menlo"],
+        zone: 3,
+        latitude: 37.4545,
+        longitude: -122.1823,
+        northbound_stop_id: 70161,
+        southbound_stop_id: 70162,
+    },
+    StationInfo {
+        station: Station::PaloAlto,
+        name: "Palo Alto",
+        aliases: &["PA"],
+        zone: 3,
+        latitude: 37.4435,
+        longitude: -122.1649,
+        northbound_stop_id: 70171,
+        southbound_stop_id: 70172,
+    },
+    StationInfo {
+        station: Station::CaliforniaAve,
+        name: "California Ave",
+        aliases: &["Cal Ave", "California Avenue"],
+        zone: 3,
+        latitude: 37.4291,
+        longitude: -122.1419,
+        northbound_stop_id: 70191,
+        southbound_stop_id: 70192,
+    },
+    StationInfo {
+        station: Station::SanAntonio,
+        name: "San Antonio",
+        aliases: &[],
+        zone: 3,
+        latitude: 37.4072,
+        longitude: -122.1072,
+        northbound_stop_id: 70201,
+        southbound_stop_id: 70202,
+    },
+    StationInfo {
+        station: Station::MountainView,
+        name: "Mountain View",
+        aliases: &["MV", "MTV"],
+        zone: 3,
+        latitude: 37.3946,
+        longitude: -122.0765,
+        northbound_stop_id: 70211,
+        southbound_stop_id: 70212,
+    },
+    StationInfo {
+        station: Station::Sunnyvale,
+        name: "Sunnyvale",
+        aliases: &[],
+        zone: 3,
+        latitude: 37.3783,
+        longitude: -122.0307,
+        northbound_stop_id: 70221,
+        southbound_stop_id: 70222,
+    },
+    StationInfo {
+        station: Station::Lawrence,
+        name: "Lawrence",
+        aliases: &[],
+        zone: 4,
+        latitude: 37.3705,
+        longitude: -121.9972,
+        northbound_stop_id: 70231,
+        southbound_stop_id: 70232,
+    },
+    StationInfo {
+        station: Station::SantaClara,
+        name: "Santa Clara",
+        aliases: &[],
+        zone: 4,
+        latitude: 37.3534,
+        longitude: -121.9363,
+        northbound_stop_id: 70241,
+        southbound_stop_id: 70242,
+    },
+    StationInfo {
+        station: Station::CollegePark,
+        name: "College Park",
+        aliases: &[],
+        zone: 4,
+        latitude: 37.3424,
+        longitude: -121.9146,
+        northbound_stop_id: 70251,
+        southbound_stop_id: 70252,
+    },
+    StationInfo {
+        station: Station::SanJoseDiridon,
+        name: "San Jose Diridon",
+        aliases: &["SJ", "San Jose", "Diridon"],
+        zone: 4,
+        latitude: 37.3297,
+        longitude: -121.9027,
+        northbound_stop_id: 70261,
+        southbound_stop_id: 70262,
+    },
+    StationInfo {
+        station: Station::Tamien,
+        name: "Tamien",
+        aliases: &[],
+        zone: 4,
+        latitude: 37.3112,
+        longitude: -121.884,
+        northbound_stop_id: 70271,
+        southbound_stop_id: 70272,
+    },
+    StationInfo {
+        station: Station::Capitol,
+        name: "Capitol",
+        aliases: &[],
+        zone: 5,
+        latitude: 37.2845,
+        longitude: -121.842,
+        northbound_stop_id: 70281,
+        southbound_stop_id: 70282,
+    },
+    StationInfo {
+        station: Station::BlossomHill,
+        name: "Blossom Hill",
+        aliases: &[],
+        zone: 5,
+        latitude: 37.2527,
+        longitude: -121.7978,
+        northbound_stop_id: 70291,
+        southbound_stop_id: 70292,
+    },
+    StationInfo {
+        station: Station::MorganHill,
+        name: "Morgan Hill",
+        aliases: &[],
+        zone: 6,
+        latitude: 37.1296,
+        longitude: -121.6505,
+        northbound_stop_id: 70301,
+        southbound_stop_id: 70302,
+    },
+    StationInfo {
+        station: Station::SanMartin,
+        name: "San Martin",
+        aliases: &[],
+        zone: 6,
+        latitude: 37.0854,
+        longitude: -121.6104,
+        northbound_stop_id: 70311,
+        southbound_stop_id: 70312,
+    },
+    StationInfo {
+        station: Station::Gilroy,
+        name: "Gilroy",
+        aliases: &[],
+        zone: 6,
+        latitude: 37.0035,
+        longitude: -121.5666,
+        northbound_stop_id: 70321,
+        southbound_stop_id: 70322,
+    },
+];
+
 impl Station {
+    pub fn all() -> impl Iterator<Item = Station> {
+        STATIONS.iter().map(|info| info.station)
+    }
+
+    pub fn info(self) -> &'static StationInfo {
+        &STATIONS[self as usize]
+    }
+
+    /// position along the line, 0 is San Francisco
+    pub fn order(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    /// great-circle distance in kilometers from the station to a point
+    pub fn distance_km(self, latitude: f64, longitude: f64) -> f64 {
+        const EARTH_RADIUS_KM: f64 = 6371.0;
+        let info = self.info();
+        let (lat1, lat2) = (info.latitude.to_radians(), latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (longitude - info.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
     pub fn get_url(self) -> &'static str {
         use Station::*;
         match self {
@@ -73,3 +437,80 @@ impl Station {
         }
     }
 }
+
+impl fmt::Display for Station {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// every station paired with its distance in kilometers from a point, closest first
+pub fn nearest(latitude: f64, longitude: f64) -> Vec<(Station, f64)> {
+    let mut ranked: Vec<(Station, f64)> = Station::all()
+        .map(|station| (station, station.distance_km(latitude, longitude)))
+        .collect();
+    ranked.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    ranked
+}
+
+/// accepts variant names, display names and aliases in any case and spacing, as well as gtfs
+/// stop ids for either platform
+impl FromStr for Station {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_named(
+            "station",
+            s,
+            STATIONS.iter().map(|info| {
+                let mut keys = vec![
+                    format!("{:?}", info.station),
+                    info.name.to_string(),
+                    info.northbound_stop_id.to_string(),
+                    info.southbound_stop_id.to_string(),
+                ];
+                keys.extend(info.aliases.iter().map(|alias| alias.to_string()));
+                (info.station, info.name.to_string(), keys)
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stations_in_line_order() {
+        for (i, station) in Station::all().enumerate() {
+            assert_eq!(station.order(), i);
+            assert_eq!(station.info().station, station);
+        }
+        assert_eq!(Station::all().count(), STATIONS.len());
+    }
+
+    #[test]
+    fn parse_station() {
+        assert_eq!("PaloAlto".parse(), Ok(Station::PaloAlto));
+        assert_eq!("palo alto".parse(), Ok(Station::PaloAlto));
+        assert_eq!("PA".parse(), Ok(Station::PaloAlto));
+        assert_eq!("22nd Street".parse(), Ok(Station::TwentySecondStreet));
+        assert_eq!("70262".parse(), Ok(Station::SanJoseDiridon));
+        let err = "Palo Altp".parse::<Station>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown station \"Palo Altp\", did you mean Palo Alto?"
+        );
+    }
+
+    #[test]
+    fn nearest_station() {
+        // University Ave & Emerson St, Palo Alto
+        let ranked = nearest(37.4457, -122.1617);
+        assert_eq!(ranked[0].0, Station::PaloAlto);
+        assert!(ranked[0].1 < 1.0);
+        assert_eq!(ranked.len(), Station::all().count());
+        // Castro St, Mountain View
+        assert_eq!(nearest(37.3925, -122.0790)[0].0, Station::MountainView);
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::parse::{parse_named, ParseError};
+
+/// text with `{name}` fields filled in when rendered
+///
+/// anything between `[` and `]` is only rendered when every field inside it has a value, so
+/// `[ arriving {eta}]` disappears when there's no arrival estimate; write `{{`, `}}`, `[[` or
+/// `]]` for the literal character
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Field(&'static str),
+    Optional(Vec<Part>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplateError {
+    UnknownField(ParseError),
+    /// a `{` or `[` without its closing `}` or `]`
+    Unclosed(char),
+    /// a `}` or `]` that closes nothing, or a `[` inside another
+    Unexpected(char),
+}
+
+impl std::error::Error for TemplateError {}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownField(e) => write!(f, "{}", e),
+            TemplateError::Unclosed(c) => write!(f, "unclosed {:?} in template", c),
+            TemplateError::Unexpected(c) => {
+                write!(f, "unexpected {:?} in template, double it to escape", c)
+            }
+        }
+    }
+}
+
+impl Template {
+    /// parses `source`, allowing only the fields in `fields`
+    pub fn parse(source: &str, fields: &[&'static str]) -> Result<Template, TemplateError> {
+        let mut parts = vec![];
+        let mut optional: Option<Vec<Part>> = None;
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            if "{}[]".contains(c) && chars.peek() == Some(&c) {
+                chars.next();
+                text.push(c);
+                continue;
+            }
+            let in_optional = optional.is_some();
+            let current = optional.as_mut().unwrap_or(&mut parts);
+            match c {
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::Unclosed('{')),
+                        }
+                    }
+                    let field = parse_named(
+                        "template field",
+                        name.trim(),
+                        fields
+                            .iter()
+                            .map(|field| (*field, field.to_string(), vec![field.to_string()])),
+                    )
+                    .map_err(TemplateError::UnknownField)?;
+                    flush(&mut text, current);
+                    current.push(Part::Field(field));
+                }
+                '[' if !in_optional => {
+                    flush(&mut text, current);
+                    optional = Some(vec![]);
+                }
+                ']' if in_optional => {
+                    let mut inner = optional.take().unwrap_or_default();
+                    flush(&mut text, &mut inner);
+                    parts.push(Part::Optional(inner));
+                }
+                '}' | '[' | ']' => return Err(TemplateError::Unexpected(c)),
+                c => text.push(c),
+            }
+        }
+        if optional.is_some() {
+            return Err(TemplateError::Unclosed('['));
+        }
+        flush(&mut text, &mut parts);
+        Ok(Template { parts })
+    }
+
+    pub fn render(&self, values: &BTreeMap<&str, String>) -> String {
+        let mut out = String::new();
+        render(&self.parts, values, &mut out);
+        out
+    }
+}
+
+fn flush(text: &mut String, parts: &mut Vec<Part>) {
+    if !text.is_empty() {
+        parts.push(Part::Text(text.clone()));
+        text.clear();
+    }
+}
+
+fn render(parts: &[Part], values: &BTreeMap<&str, String>, out: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Field(field) => {
+                if let Some(value) = values.get(field) {
+                    out.push_str(value);
+                }
+            }
+            Part::Optional(inner) => {
+                let complete = inner.iter().all(|part| match part {
+                    Part::Field(field) => values.contains_key(field),
+                    _ => true,
+                });
+                if complete {
+                    render(inner, values, out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FIELDS: &[&str] = &["train", "eta"];
+
+    #[test]
+    fn render_optional_sections() {
+        let template = Template::parse("train {train}[, arriving {eta}] {{ok}}", FIELDS).unwrap();
+        let mut values = BTreeMap::new();
+        values.insert("train", "101".to_string());
+        assert_eq!(template.render(&values), "train 101 {ok}");
+        values.insert("eta", "9:41AM".to_string());
+        assert_eq!(template.render(&values), "train 101, arriving 9:41AM {ok}");
+    }
+
+    #[test]
+    fn parse_errors() {
+        match Template::parse("{tarin}", FIELDS) {
+            Err(TemplateError::UnknownField(e)) => assert!(e.to_string().contains("train")),
+            other => panic!("expected unknown field, got {:?}", other),
+        }
+        assert_eq!(
+            Template::parse("{train", FIELDS),
+            Err(TemplateError::Unclosed('{'))
+        );
+        assert_eq!(
+            Template::parse("[a [b]]", FIELDS),
+            Err(TemplateError::Unexpected('['))
+        );
+        assert_eq!(
+            Template::parse("[{eta}", FIELDS),
+            Err(TemplateError::Unclosed('['))
+        );
+    }
+}
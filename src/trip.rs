@@ -0,0 +1,293 @@
+use std::cmp::Reverse;
+
+use crate::caltrain_status::{CaltrainStatus, Direction, IncomingTrain, TrainType};
+use crate::schedule::{direction_between, minutes_to_arrival, run_minutes};
+use crate::station::Station;
+
+/// least time to allow for changing trains
+pub const TRANSFER_MINUTES: u16 = 2;
+
+/// one train ridden between two stations, times are minutes from when the status was fetched
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leg {
+    pub train: u16,
+    pub train_type: TrainType,
+    pub board: Station,
+    pub departs_in: u16,
+    pub alight: Station,
+    pub arrives_in: u16,
+    /// `departs_in` comes from the schedule because `board` had no live status
+    pub scheduled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trip {
+    pub legs: Vec<Leg>,
+}
+
+impl Trip {
+    pub fn departs_in(&self) -> u16 {
+        self.legs[0].departs_in
+    }
+
+    pub fn arrives_in(&self) -> u16 {
+        self.legs[self.legs.len() - 1].arrives_in
+    }
+
+    fn scheduled_legs(&self) -> usize {
+        self.legs.iter().filter(|leg| leg.scheduled).count()
+    }
+
+    /// at least as good on arrival, departure, number of trains and live departures, and better on
+    /// one of them, so equally good trips through different stations are all kept
+    fn dominates(&self, other: &Trip) -> bool {
+        let no_worse = self.arrives_in() <= other.arrives_in()
+            && self.departs_in() >= other.departs_in()
+            && self.legs.len() <= other.legs.len()
+            && self.scheduled_legs() <= other.scheduled_legs();
+        let better = self.arrives_in() < other.arrives_in()
+            || self.departs_in() > other.departs_in()
+            || self.legs.len() < other.legs.len()
+            || self.scheduled_legs() < other.scheduled_legs();
+        no_worse && better
+    }
+}
+
+/// stations between `from` and `to` where an express train usually stops on its way to `to`, the
+/// only places worth changing trains
+pub fn transfer_stations(from: Station, to: Station) -> Vec<Station> {
+    let (low, high) = (from.order().min(to.order()), from.order().max(to.order()));
+    Station::all()
+        .filter(|s| s.order() > low && s.order() < high)
+        .filter(|s| {
+            run_minutes(TrainType::BabyBullet, *s, to).is_some()
+                || run_minutes(TrainType::Limited, *s, to).is_some()
+        })
+        .collect()
+}
+
+fn leg(
+    train: &IncomingTrain,
+    direction: Direction,
+    board: Station,
+    alight: Station,
+    scheduled: bool,
+) -> Option<Leg> {
+    Some(Leg {
+        train: train.get_id(),
+        train_type: train.get_train_type(),
+        board,
+        departs_in: train.get_min_till_departure(),
+        alight,
+        arrives_in: minutes_to_arrival(train, direction, board, alight)?,
+        scheduled,
+    })
+}
+
+/// direct and single transfer trips from `from` to `to` leaving at least `depart_after` minutes
+/// from now, earliest arrival first
+///
+/// departures come from the live status of each station in `live`, run times from the typical
+/// schedule; a station missing from `live` gets its departures from the schedule instead, timed
+/// from the trains live at the stations before it; trips beaten by another that leaves no earlier,
+/// arrives no later, doesn't need more trains and relies no more on the schedule are dropped
+pub fn plan(
+    from: Station,
+    to: Station,
+    depart_after: u16,
+    live: &[(Station, CaltrainStatus)],
+) -> Vec<Trip> {
+    let direction = match direction_between(from, to) {
+        Some(direction) => direction,
+        None => return vec![],
+    };
+    let listed = |status: &CaltrainStatus| -> Vec<IncomingTrain> {
+        let (northbound, southbound) = status.get_status();
+        match direction {
+            Direction::Northbound => northbound.to_vec(),
+            Direction::Southbound => southbound.to_vec(),
+        }
+    };
+    // the trains leaving `station` and whether their departures come from the schedule
+    let trains_at = |station: Station| -> (Vec<IncomingTrain>, bool) {
+        if let Some((_, status)) = live.iter().find(|(s, _)| *s == station) {
+            return (listed(status), false);
+        }
+        // nearest station first, so each train is timed from its freshest live estimate
+        let mut upstream: Vec<&(Station, CaltrainStatus)> = live
+            .iter()
+            .filter(|(s, _)| direction_between(*s, station) == Some(direction))
+            .collect();
+        upstream.sort_by_key(|(s, _)| (s.order() as isize - station.order() as isize).abs());
+        let mut estimated: Vec<IncomingTrain> = vec![];
+        for (s, status) in upstream {
+            for train in listed(status) {
+                if estimated
+                    .iter()
+                    .any(|known| known.get_id() == train.get_id())
+                {
+                    continue;
+                }
+                if let Some(departs_in) = minutes_to_arrival(&train, direction, *s, station) {
+                    estimated.push(IncomingTrain::new(
+                        train.get_id(),
+                        train.get_train_type(),
+                        departs_in,
+                    ));
+                }
+            }
+        }
+        (estimated, true)
+    };
+
+    let mut trips = vec![];
+    let (departing, from_scheduled) = trains_at(from);
+    for first in departing
+        .iter()
+        .filter(|train| train.get_min_till_departure() >= depart_after)
+    {
+        if let Some(direct) = leg(first, direction, from, to, from_scheduled) {
+            trips.push(Trip { legs: vec![direct] });
+        }
+        for transfer in transfer_stations(from, to) {
+            let first_leg = match leg(first, direction, from, transfer, from_scheduled) {
+                Some(first_leg) => first_leg,
+                None => continue,
+            };
+            let (connecting, scheduled) = trains_at(transfer);
+            for second in connecting {
+                if second.get_id() == first.get_id()
+                    || second.get_min_till_departure() < first_leg.arrives_in + TRANSFER_MINUTES
+                {
+                    continue;
+                }
+                if let Some(second_leg) = leg(&second, direction, transfer, to, scheduled) {
+                    trips.push(Trip {
+                        legs: vec![first_leg.clone(), second_leg],
+                    });
+                }
+            }
+        }
+    }
+
+    let mut ranked: Vec<Trip> = trips
+        .iter()
+        .filter(|trip| !trips.iter().any(|other| other.dominates(trip)))
+        .cloned()
+        .collect();
+    ranked.sort_by_key(|trip| {
+        (
+            trip.arrives_in(),
+            trip.legs.len(),
+            Reverse(trip.departs_in()),
+        )
+    });
+    ranked.dedup();
+    ranked
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn page(southbound: &[(u16, &str, u16)], northbound: &[(u16, &str, u16)]) -> CaltrainStatus {
+        let table = |trains: &[(u16, &str, u16)]| {
+            let rows: Vec<String> = trains
+                .iter()
+                .map(|(id, ttype, min)| {
+                    format!(
+                        "<tr class=\"ipf-st-ip-trains-subtable-tr\">\
+                         <td class=\"ipf-st-ip-trains-subtable-td-id\">{}</td>\
+                         <td class=\"ipf-st-ip-trains-subtable-td-type\">{}</td>\
+                         <td class=\"ipf-st-ip-trains-subtable-td-arrivaltime\">{} min.</td></tr>",
+                        id, ttype, min
+                    )
+                })
+                .collect();
+            format!(
+                "<td><table class=\"ipf-st-ip-trains-subtable\">{}</table></td>",
+                rows.concat()
+            )
+        };
+        CaltrainStatus::from_html(format!(
+            "<table><tr>{}{}</tr></table>",
+            table(southbound),
+            table(northbound)
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn plan_direct_and_transfer() {
+        // a local leaves california ave right away, a bullet leaves palo alto 10 minutes later
+        let live = vec![
+            (
+                Station::CaliforniaAve,
+                page(&[], &[(101, "Local", 1), (103, "Local", 40)]),
+            ),
+            (
+                Station::PaloAlto,
+                page(&[], &[(101, "Local", 4), (301, "Baby Bullet", 10)]),
+            ),
+        ];
+        let trips = plan(Station::CaliforniaAve, Station::SanFrancisco, 0, &live);
+
+        assert_eq!(trips[0].legs.len(), 2);
+        assert_eq!(trips[0].legs[0].train, 101);
+        assert_eq!(trips[0].legs[0].alight, Station::PaloAlto);
+        assert_eq!(trips[0].legs[1].train, 301);
+        assert_eq!(trips[0].arrives_in(), 10 + 38);
+        assert_eq!(trips[1].legs.len(), 1);
+        assert_eq!(trips[1].arrives_in(), 1 + 60);
+        // leaving later is still an option, even though it arrives last
+        assert_eq!(trips.len(), 3);
+        assert_eq!(trips[2].legs[0].train, 103);
+        assert_eq!(trips[2].arrives_in(), 40 + 60);
+    }
+
+    #[test]
+    fn plan_from_station_expresses_skip() {
+        let live = vec![
+            (
+                Station::Atherton,
+                page(&[], &[(101, "Local", 1), (103, "Local", 40)]),
+            ),
+            (
+                Station::RedwoodCity,
+                page(&[], &[(101, "Local", 5), (301, "Baby Bullet", 10)]),
+            ),
+        ];
+        let trips = plan(Station::Atherton, Station::SanFrancisco, 0, &live);
+
+        assert_eq!(trips[0].legs.len(), 2);
+        assert_eq!(trips[0].legs[0].train, 101);
+        assert_eq!(trips[0].legs[0].alight, Station::RedwoodCity);
+        assert_eq!(trips[0].legs[1].train, 301);
+        assert_eq!(trips[0].arrives_in(), 10 + 30);
+    }
+
+    #[test]
+    fn plan_estimates_transfer_station_without_status() {
+        // palo alto's status is missing, the bullet's departure there comes from mountain view
+        let live = vec![
+            (Station::SanAntonio, page(&[], &[(101, "Local", 1)])),
+            (Station::MountainView, page(&[], &[(301, "Baby Bullet", 4)])),
+        ];
+        let trips = plan(Station::SanAntonio, Station::SanFrancisco, 0, &live);
+
+        let via_palo_alto = trips
+            .iter()
+            .find(|trip| trip.legs[0].alight == Station::PaloAlto)
+            .unwrap();
+        assert_eq!(via_palo_alto.legs[1].train, 301);
+        assert_eq!(via_palo_alto.legs[1].departs_in, 4 + 6);
+        assert_eq!(via_palo_alto.arrives_in(), 4 + 44);
+        assert_eq!(trips[0].arrives_in(), 4 + 44);
+    }
+
+    #[test]
+    fn plan_wrong_way() {
+        let live = vec![(Station::PaloAlto, page(&[], &[(101, "Local", 4)]))];
+        assert!(plan(Station::PaloAlto, Station::Gilroy, 0, &live).is_empty());
+    }
+}